@@ -0,0 +1,254 @@
+//! Allocators for data with a limited lifetime, which never touch the global
+//! allocator or its lock. Use them through `allocator_api`, e.g. `Box::new_in(x, &arena)`.
+
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::Cell,
+    marker::PhantomData,
+    mem::{align_of, size_of, ManuallyDrop},
+    ptr::{null_mut, NonNull},
+};
+
+use crate::syscalls::{self, MAdvice, MMapFlags, MProt, SyscallResult};
+
+/// Reserve `size` bytes of zeroed memory, which only take up space once they are used
+unsafe fn reserve(size: usize) -> SyscallResult<*mut u8> {
+    syscalls::mmap(
+        null_mut(),
+        size,
+        MProt::READ | MProt::WRITE,
+        MMapFlags::ANONYMOUS | MMapFlags::PRIVATE | MMapFlags::NORESERVE,
+        -1,
+        0,
+    )
+}
+
+fn slice_ptr(ptr: *mut u8, len: usize) -> NonNull<[u8]> {
+    // Safety: all callers pass pointers into a mapping
+    unsafe { NonNull::new_unchecked(core::ptr::slice_from_raw_parts_mut(ptr, len)) }
+}
+
+/// A bump allocator over a fixed reservation.
+/// Freeing only returns memory if it was the latest allocation,
+/// everything else is freed at once by `reset` or dropping the arena.
+pub struct Arena {
+    base: *mut u8,
+    capacity: usize,
+    /// Bytes in use from the start of the reservation
+    top: Cell<usize>,
+}
+
+// Safety: allocations borrow the arena, so it can only move between threads when there are none
+unsafe impl Send for Arena {}
+
+impl Arena {
+    /// Reserve `capacity` bytes of address space. Memory is only used once it is allocated.
+    pub fn new(capacity: usize) -> SyscallResult<Self> {
+        assert!(capacity > 0, "arena capacity must be non-zero");
+
+        Ok(Self {
+            base: unsafe { reserve(capacity)? },
+            capacity,
+            top: Cell::new(0),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of bytes allocated since the last `reset`, including padding
+    pub fn used(&self) -> usize {
+        self.top.get()
+    }
+
+    /// Free all allocations and return their memory to the kernel
+    pub fn reset(&mut self) -> SyscallResult<()> {
+        unsafe { syscalls::madvise(self.base, self.top.get(), MAdvice::DontNeed)? };
+
+        self.top.set(0);
+
+        Ok(())
+    }
+
+    /// Returns true if nothing was allocated after `ptr`
+    fn is_latest(&self, ptr: NonNull<u8>, size: usize) -> bool {
+        ptr.as_ptr() as usize + size == self.base as usize + self.top.get()
+    }
+
+    /// Move the top to `size` bytes after `start`, if that fits into the reservation
+    fn set_top(&self, start: usize, size: usize) -> Result<(), AllocError> {
+        let top = start.checked_add(size).ok_or(AllocError)?;
+
+        if top > self.capacity {
+            return Err(AllocError);
+        }
+
+        self.top.set(top);
+
+        Ok(())
+    }
+}
+
+unsafe impl Allocator for &Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let top = self.base as usize + self.top.get();
+
+        let start = top.checked_add(layout.align() - 1).ok_or(AllocError)? & !(layout.align() - 1);
+
+        let start = start - self.base as usize;
+
+        self.set_top(start, layout.size())?;
+
+        Ok(slice_ptr(unsafe { self.base.add(start) }, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_latest(ptr, layout.size()) {
+            self.top.set(ptr.as_ptr().offset_from(self.base) as usize);
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // The latest allocation can grow in place
+        if self.is_latest(ptr, old_layout.size())
+            && ptr.as_ptr().align_offset(new_layout.align()) == 0
+        {
+            let start = ptr.as_ptr().offset_from(self.base) as usize;
+
+            if self.set_top(start, new_layout.size()).is_ok() {
+                return Ok(slice_ptr(ptr.as_ptr(), new_layout.size()));
+            }
+        }
+
+        let new = self.allocate(new_layout)?;
+
+        core::ptr::copy_nonoverlapping(ptr.as_ptr(), new.as_ptr() as *mut u8, old_layout.size());
+
+        self.deallocate(ptr, old_layout);
+
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if ptr.as_ptr().align_offset(new_layout.align()) != 0 {
+            let new = self.allocate(new_layout)?;
+
+            core::ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                new.as_ptr() as *mut u8,
+                new_layout.size(),
+            );
+
+            return Ok(new);
+        }
+
+        if self.is_latest(ptr, old_layout.size()) {
+            let start = ptr.as_ptr().offset_from(self.base) as usize;
+
+            self.top.set(start + new_layout.size());
+        }
+
+        Ok(slice_ptr(ptr.as_ptr(), new_layout.size()))
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { syscalls::munmap(self.base, self.capacity) }.expect("Failed to unmap arena");
+    }
+}
+
+/// A slot of a `Pool`, which links to the next free slot while it is free
+union Slot<T> {
+    value: ManuallyDrop<T>,
+    next: *mut Slot<T>,
+}
+
+/// A fixed number of slots for values of type `T`.
+/// Allocations of anything that does not fit into a `T` fail.
+pub struct Pool<T> {
+    slots: *mut Slot<T>,
+    capacity: usize,
+    /// Slots from here on were never allocated
+    n_initialized: Cell<usize>,
+    /// The most recently freed slot
+    free: Cell<*mut Slot<T>>,
+    _marker: PhantomData<T>,
+}
+
+// Safety: allocations borrow the pool, so it can only move between threads when there are none
+unsafe impl<T: Send> Send for Pool<T> {}
+
+impl<T> Pool<T> {
+    /// Reserve space for `capacity` values. Memory is only used once a slot is allocated.
+    pub fn new(capacity: usize) -> SyscallResult<Self> {
+        assert!(capacity > 0, "pool capacity must be non-zero");
+
+        let size = capacity
+            .checked_mul(size_of::<Slot<T>>())
+            .expect("pool capacity overflows");
+
+        Ok(Self {
+            slots: unsafe { reserve(size)? } as *mut Slot<T>,
+            capacity,
+            n_initialized: Cell::new(0),
+            free: Cell::new(null_mut()),
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+unsafe impl<T> Allocator for &Pool<T> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() > size_of::<Slot<T>>() || layout.align() > align_of::<Slot<T>>() {
+            return Err(AllocError);
+        }
+
+        let slot = self.free.get();
+
+        let slot = if !slot.is_null() {
+            self.free.set(unsafe { (*slot).next });
+
+            slot
+        } else if self.n_initialized.get() < self.capacity {
+            let slot = unsafe { self.slots.add(self.n_initialized.get()) };
+
+            self.n_initialized.set(self.n_initialized.get() + 1);
+
+            slot
+        } else {
+            return Err(AllocError);
+        };
+
+        Ok(slice_ptr(slot as *mut u8, size_of::<Slot<T>>()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+        let slot = ptr.as_ptr() as *mut Slot<T>;
+
+        (*slot).next = self.free.get();
+        self.free.set(slot);
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        unsafe { syscalls::munmap(self.slots as *mut u8, self.capacity * size_of::<Slot<T>>()) }
+            .expect("Failed to unmap pool");
+    }
+}
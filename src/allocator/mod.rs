@@ -0,0 +1,2154 @@
+mod local;
+
+pub use local::{Arena, Pool};
+
+use crate::{
+    backtrace,
+    stack_protection::PAGESIZE,
+    start::RUNTIME_OPTIONS,
+    sync::*,
+    syscalls,
+    syscalls::{helper::SyscallErrorKind, *},
+    tls,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    isize,
+    mem::{size_of, MaybeUninit},
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, Ordering},
+    usize,
+};
+const UNALLOCATED_DATA_SENTINEL: u8 = 0x42;
+
+// TODO: switch this to a linked list arena approach?
+
+// adjustable contants
+const BLOCK_SHIFT: usize = 14;
+// Allow a maximum loss of 12.5%. For everything more, mmap
+// Maximum loss is acchieved with minimum (1) and maximum (2096) chunks size
+const MMAP_THRESHOLD_SHIFT: usize = BLOCK_SHIFT - 3;
+/// Chunks are aligned to their size, up to this
+const ALLOCATOR_ALIGN: usize = PAGESIZE;
+/// Smaller chunks would make blocks mostly header, and slow to search
+const MIN_CHUNK_SHIFT: usize = 3;
+/// The address space reserved by `HeapBackend::Mmap`
+const MMAP_HEAP_RESERVATION: usize = 1 << 36;
+/// The number of empty blocks `HeapBackend::Mmap` keeps before returning their memory
+const N_KEEP_EMPTY_BLOCKS: usize = 8;
+/// The size of the huge pages the allocator uses, which is the default on x86_64.
+/// `HugePages::HugeTlb` asks for this size explicitly, since the system default can be 1 GiB.
+/// Allocations smaller than this are never put into huge pages.
+pub const HUGE_PAGE_SIZE: usize = 1 << 21;
+
+// derived constants
+const MMAP_THRESHOLD: usize = 1 << MMAP_THRESHOLD_SHIFT;
+
+const BLOCK_SIZE: usize = 1 << BLOCK_SHIFT;
+const BLOCK_LOWER_MASK: usize = BLOCK_SIZE - 1;
+
+const MAX_CHUNK_SHIFT: usize = BLOCK_SHIFT - 1;
+const N_CHUNK_SHIFT_BITS: usize =
+    core::mem::size_of::<usize>() * 8 - MAX_CHUNK_SHIFT.leading_zeros() as usize;
+
+const CHUNK_SHIFT_MASK: u8 = (1 << N_CHUNK_SHIFT_BITS) - 1;
+const CHUNK_POPULATED_MASK: u8 = 1 << N_CHUNK_SHIFT_BITS;
+const CHUNK_FULL_MASK: u8 = 1 << (N_CHUNK_SHIFT_BITS + 1);
+const CHUNK_HEADER_MASK: u8 = !(CHUNK_SHIFT_MASK | CHUNK_POPULATED_MASK);
+
+const STATUS_BITS_SIZE: usize = 2;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(transparent)]
+struct Block([u8; BLOCK_SIZE]);
+
+impl Block {
+    /// An empty block of `1 << chunk_shift` byte chunks
+    fn new(chunk_shift: usize) -> Self {
+        debug_assert!(chunk_shift <= MAX_CHUNK_SHIFT);
+
+        let mut block = Block([0; BLOCK_SIZE]);
+        block.0[0] = chunk_shift as u8;
+
+        if UNALLOCATED_DATA_SENTINEL != 0 {
+            let size = block.chunk_size();
+            let n = block.n_header_chunks();
+
+            for dest in block.0.iter_mut().skip(n * size) {
+                *dest = UNALLOCATED_DATA_SENTINEL;
+            }
+
+            assert!(block.check_if_empty());
+        }
+
+        block
+    }
+
+    #[inline]
+    fn chunk_shift(&self) -> u8 {
+        self.0[0] & CHUNK_SHIFT_MASK
+    }
+
+    #[inline]
+    fn chunk_size(&self) -> usize {
+        1 << self.chunk_shift() as usize
+    }
+
+    #[inline]
+    fn n_chunks(&self) -> usize {
+        BLOCK_SIZE / self.chunk_size()
+    }
+
+    fn free_header_bits(&self) -> usize {
+        self.chunk_size() * 8 - N_CHUNK_SHIFT_BITS - STATUS_BITS_SIZE
+    }
+
+    #[inline]
+    fn n_header_chunks(&self) -> usize {
+        // we have 8 => 2^3 bits for each byte so add 3 to the exponent
+        let chunk_bit_shift = self.chunk_shift() + 3;
+
+        let free_header_bits = self.free_header_bits();
+
+        let needed_bits = self.n_chunks().saturating_sub(free_header_bits);
+
+        // calculate needed header chunks for all chunks including headers
+        let naive_n_headers = ceil_shr(needed_bits, chunk_bit_shift as u32);
+
+        // calculate actual needed headers chunks
+        let n_headers = ceil_shr(needed_bits - naive_n_headers, chunk_bit_shift as u32);
+
+        if n_headers < naive_n_headers {
+            // we saved one or more chunks
+
+            // make sure the chunks we saved don't require additional header chunks
+            let n_headers_with_saved_chunks =
+                ceil_shr(needed_bits - n_headers, chunk_bit_shift as u32);
+
+            // if we would require additional chunks, give up and return the naive solution
+            if n_headers_with_saved_chunks != n_headers {
+                return naive_n_headers + 1;
+            }
+        }
+
+        // we can fit all free chunks into our optimized headers. (yay)
+        n_headers + 1
+    }
+
+    #[inline]
+    fn first_chunk(&self) -> usize {
+        self.n_header_chunks()
+    }
+
+    // TODO: optimize these by writing ranges at a time
+
+    #[inline]
+    fn get_header_free_bit(&self, j: usize) -> bool {
+        let i = j + N_CHUNK_SHIFT_BITS + STATUS_BITS_SIZE;
+        let byte = i / 8;
+        let offset = i % 8;
+
+        let res = self.0[byte] & (1 << offset);
+
+        // trace!("[{:5}]: {} - {} | {:#010b} => {}", j, byte, offset, self.0[byte],
+        // (res != 0) as u8);
+
+        res == 0
+    }
+
+    #[inline]
+    fn set_header_free_bit(&mut self, j: usize, val: bool) {
+        let i = j + N_CHUNK_SHIFT_BITS + STATUS_BITS_SIZE;
+        let byte = i / 8;
+        let offset = i % 8;
+
+        // trace!("[{:5}]: {} - {} | {} => {:#010b}", j, byte, offset, (!val) as u8,
+        // self.0[byte]);
+
+        if val {
+            self.0[byte] &= !(1 << offset);
+        } else {
+            self.0[byte] |= 1 << offset;
+        }
+
+        // trace!("[{:5}]: {} - {} |   => {:#010b}", j, byte, offset,
+        // self.0[byte]);
+    }
+
+    #[inline]
+    fn get_chunk_free_bit(&self, j: usize) -> bool {
+        let byte = self.chunk_size() + j / 8;
+        let offset = j % 8;
+
+        let res = self.0[byte] & (1 << offset);
+
+        /*
+        trace!(
+            "[{:5}]: {} - {} | {:#010b} => {}",
+            j,
+            byte,
+            offset,
+            self.0[byte],
+            (res != 0) as u8
+        );
+        */
+
+        res == 0
+    }
+
+    #[inline]
+    fn set_chunk_free_bit(&mut self, j: usize, val: bool) {
+        let byte = self.chunk_size() + j / 8;
+        let offset = j % 8;
+
+        // trace!("[{:5}]: {} - {} | {} => {:#010b}", j, byte, offset, (!val) as u8,
+        // self.0[byte]);
+
+        if val {
+            self.0[byte] &= !(1 << offset);
+        } else {
+            self.0[byte] |= 1 << offset;
+        }
+
+        if val {
+            self.0[byte] &= !(1 << offset);
+        } else {
+            self.0[byte] |= 1 << offset;
+        }
+
+        // trace!("[{:5}]: {} - {} |   => {:#010b}", j, byte, offset,
+        // self.0[byte]);
+    }
+
+    fn alloc(&mut self, size: usize) -> Option<usize> {
+        if self.is_full() {
+            return None;
+        }
+
+        let n_needed = ceil_shr(size, self.chunk_shift() as u32);
+
+        let n_free_bits = self.n_chunks() - self.n_header_chunks();
+        let n_header_bits = self.free_header_bits();
+
+        #[derive(Debug, Clone, Copy)]
+        struct FreeRegion {
+            start: usize,
+            size: usize,
+        }
+
+        let mut current = FreeRegion { start: 0, size: 0 };
+
+        let mut best: Option<FreeRegion> = None;
+
+        for i in 0..=n_free_bits {
+            // trace!("{} @ {}", current.size, current.start);
+
+            let is_free = if i == n_free_bits {
+                false
+            } else if i < n_header_bits {
+                self.get_header_free_bit(i)
+            } else {
+                self.get_chunk_free_bit(i - n_header_bits)
+            };
+
+            if is_free {
+                current.size += 1;
+            } else {
+                // only regions the allocation fits into
+                if current.size >= n_needed {
+                    // trace!("[{:5}, {:5}]", current.start, current.start + current.size - 1);
+
+                    if let Some(best) = best.as_mut() {
+                        if current.size < best.size {
+                            *best = current;
+                        }
+                    } else {
+                        best = Some(current);
+                    }
+
+                    if current.size == n_needed {
+                        // TODO: uncomment
+                        // break;
+                    }
+                }
+
+                current = FreeRegion {
+                    start: i + 1,
+                    size: 0,
+                };
+            }
+        }
+
+        if let Some(best) = best {
+            for i in best.start..best.start + n_needed {
+                if i < n_header_bits {
+                    self.set_header_free_bit(i, false);
+                } else {
+                    self.set_chunk_free_bit(i - n_header_bits, false);
+                }
+            }
+
+            self.0[0] |= CHUNK_POPULATED_MASK;
+
+            if self.check_if_full() {
+                self.0[0] |= CHUNK_FULL_MASK;
+            }
+
+            Some((self.first_chunk() + best.start) * self.chunk_size())
+        } else {
+            None
+        }
+    }
+
+    fn free(&mut self, offset: usize, size: usize) {
+        // dbg!(offset, size);
+
+        let start_chunk = (offset / self.chunk_size()) - self.n_header_chunks();
+        let n_chunks = ceil_shr(size, self.chunk_shift() as u32);
+        let n_header_bits = self.free_header_bits();
+
+        // dbg!(start_chunk, n_chunks);
+
+        for i in start_chunk..start_chunk + n_chunks {
+            if i < n_header_bits {
+                self.set_header_free_bit(i, true);
+            } else {
+                self.set_chunk_free_bit(i - n_header_bits, true);
+            }
+        }
+
+        self.0[0] &= !CHUNK_FULL_MASK;
+
+        if self.check_if_empty() {
+            debug_assert_eq!(self.n_bytes_allocated(), 0);
+            self.0[0] &= !CHUNK_POPULATED_MASK;
+        } else {
+            debug_assert_ne!(self.n_bytes_allocated(), 0);
+        }
+    }
+
+    /// Grow or shrink the allocation at `offset` from `old_size` to `new_size` bytes
+    /// without moving it. Returns false if the chunks after it are not free.
+    fn resize(&mut self, offset: usize, old_size: usize, new_size: usize) -> bool {
+        let start_chunk = (offset / self.chunk_size()) - self.n_header_chunks();
+        let n_old = ceil_shr(old_size, self.chunk_shift() as u32);
+        let n_new = ceil_shr(new_size, self.chunk_shift() as u32);
+
+        if n_new <= n_old {
+            if n_new < n_old {
+                self.free(
+                    offset + n_new * self.chunk_size(),
+                    (n_old - n_new) * self.chunk_size(),
+                );
+            }
+
+            return true;
+        }
+
+        let n_free_bits = self.n_chunks() - self.n_header_chunks();
+        let n_header_bits = self.free_header_bits();
+
+        if start_chunk + n_new > n_free_bits {
+            return false;
+        }
+
+        let is_free = |i: usize| {
+            if i < n_header_bits {
+                self.get_header_free_bit(i)
+            } else {
+                self.get_chunk_free_bit(i - n_header_bits)
+            }
+        };
+
+        if !(start_chunk + n_old..start_chunk + n_new).all(is_free) {
+            return false;
+        }
+
+        for i in start_chunk + n_old..start_chunk + n_new {
+            if i < n_header_bits {
+                self.set_header_free_bit(i, false);
+            } else {
+                self.set_chunk_free_bit(i - n_header_bits, false);
+            }
+        }
+
+        if self.check_if_full() {
+            self.0[0] |= CHUNK_FULL_MASK;
+        }
+
+        true
+    }
+
+    fn check_if_empty(&self) -> bool {
+        let n_free_bits = self.n_chunks() - self.n_header_chunks();
+        let n_embedded = 8 - N_CHUNK_SHIFT_BITS - STATUS_BITS_SIZE;
+        let n_remaining_free_bits = n_free_bits - n_embedded;
+
+        if self.0[0] & CHUNK_HEADER_MASK != 0 {
+            // debug!("used chunk in embedded bits");
+            return false;
+        }
+
+        let n_full = n_remaining_free_bits / 8;
+
+        let tail_index = n_full + 1;
+        for i in 1..tail_index {
+            if self.0[i] != 0 {
+                // debug!("used chunk in full bits ({})", i);
+                return false;
+            }
+        }
+
+        let n_in_last = n_remaining_free_bits % 8;
+        if self.0[tail_index] & ((1 << n_in_last) - 1) != 0 {
+            // debug!("used chunk in tail bits");
+            return false;
+        }
+
+        // debug!("chunk is empty");
+
+        true
+    }
+
+    fn check_if_full(&self) -> bool {
+        let n_free_bits = self.n_chunks() - self.n_header_chunks();
+        let n_embedded = 8 - N_CHUNK_SHIFT_BITS - STATUS_BITS_SIZE;
+        let n_remaining_free_bits = n_free_bits - n_embedded;
+
+        if self.0[0] & CHUNK_HEADER_MASK != CHUNK_HEADER_MASK {
+            // debug!("non full chunk in embedded bits");
+            return false;
+        }
+
+        let n_full = n_remaining_free_bits / 8;
+
+        let tail_index = n_full + 1;
+        for i in 1..tail_index {
+            if self.0[i] != u8::MAX {
+                // debug!("non full chunk in full bits ({})", i);
+                return false;
+            }
+        }
+
+        let n_in_last = n_remaining_free_bits % 8;
+        let mask = (1 << n_in_last) - 1;
+        if self.0[tail_index] & mask != mask {
+            // debug!("non full chunk in tail bits");
+            return false;
+        }
+
+        // debug!("chunk is full");
+
+        true
+    }
+
+    fn n_chunks_used(&self) -> usize {
+        let n_free_bits = self.n_chunks() - self.n_header_chunks();
+        let n_header_bits = self.free_header_bits();
+
+        let mut n_chunks_in_use = 0;
+
+        for i in 0..n_free_bits {
+            let is_free = if i < n_header_bits {
+                self.get_header_free_bit(i)
+            } else {
+                self.get_chunk_free_bit(i - n_header_bits)
+            };
+
+            if !is_free {
+                n_chunks_in_use += 1;
+            }
+        }
+
+        n_chunks_in_use
+    }
+
+    fn n_bytes_allocated(&self) -> usize {
+        self.n_chunks_used() * self.chunk_size()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0[0] & CHUNK_POPULATED_MASK == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.0[0] & CHUNK_FULL_MASK != 0
+    }
+
+    fn align(&self) -> usize {
+        self.chunk_size().min(ALLOCATOR_ALIGN)
+    }
+
+    fn is_chunk_free(&self, i: usize) -> bool {
+        let n_header_bits = self.free_header_bits();
+
+        if i < n_header_bits {
+            self.get_header_free_bit(i)
+        } else {
+            self.get_chunk_free_bit(i - n_header_bits)
+        }
+    }
+
+    /// The chunks after the headers
+    fn data(&self) -> &[u8] {
+        &self.0[self.first_chunk() * self.chunk_size()..]
+    }
+
+    fn list(&self) -> Option<BlockList> {
+        if self.is_full() {
+            None
+        } else if self.is_empty() {
+            Some(BlockList::Empty)
+        } else {
+            Some(BlockList::Partial(self.chunk_shift() as usize))
+        }
+    }
+}
+
+#[inline]
+// divides `x` by 2^shift, rounding up
+fn ceil_shr(x: usize, shift: u32) -> usize {
+    let n = x >> shift;
+
+    // check if any of the lower bits were set => shifted away
+    let mask = (1 << shift) - 1;
+    if x & mask != 0 {
+        n + 1
+    } else {
+        n
+    }
+}
+
+/// Marks the end of a block list
+const NO_BLOCK: u32 = u32::MAX;
+
+/// The neighbours of a block in its `BlockList`
+#[derive(Debug, Clone, Copy)]
+struct BlockLinks {
+    prev: u32,
+    next: u32,
+    /// The block is empty and its memory was returned to the kernel
+    released: bool,
+}
+
+/// The list a block is in. Full blocks are not in any list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockList {
+    Empty,
+    /// Blocks with free chunks, by their chunk shift
+    Partial(usize),
+}
+
+/// Where the allocator gets the memory for its blocks from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapBackend {
+    /// Grow the process `brk`. Memory can only be returned from the top of the heap.
+    Brk,
+    /// Reserve address space with `mmap`. The memory of empty blocks is
+    /// returned to the kernel, wherever they are in the heap.
+    Mmap,
+}
+
+/// Extra checks of the global allocator, which panic with the offending address if
+/// - memory is written to after it was freed
+/// - memory is freed twice
+/// - memory is freed with a different `Layout` than it was allocated with
+///
+/// Per-thread caches are not used in debug mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugAlloc {
+    /// The number of freed allocations to keep from being reused, so that
+    /// dangling pointers can't corrupt new allocations for a while.
+    /// Quarantined allocations still count as allocated in `stats()`.
+    pub quarantine: usize,
+}
+
+/// Whether the global allocator asks the kernel for huge pages, which need
+/// far fewer TLB entries than `PAGESIZE` pages to cover large allocations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePages {
+    /// Only use normal pages
+    Off,
+    /// Mark allocations of at least `HUGE_PAGE_SIZE` bytes and the heap of
+    /// `HeapBackend::Mmap` with `MADV_HUGEPAGE`, so that the kernel backs them with
+    /// transparent huge pages where it can. Does nothing if those are disabled.
+    Transparent,
+    /// Like `Transparent`, but map allocations of at least `HUGE_PAGE_SIZE` bytes with
+    /// `MAP_HUGETLB`, from the 2 MiB pages reserved in
+    /// `/sys/kernel/mm/hugepages/hugepages-2048kB/nr_hugepages`, while there are any.
+    /// These allocations are rounded up to whole huge pages.
+    HugeTlb,
+}
+
+/// Only written by `init`
+static mut HUGE_PAGES: HugePages = HugePages::Off;
+
+/// A freed allocation in the quarantine of `DebugAlloc`
+#[derive(Debug, Clone, Copy)]
+struct Quarantined {
+    ptr: *mut u8,
+    size: usize,
+}
+
+static DEBUG_CHECKS: AtomicBool = AtomicBool::new(false);
+
+/// Returns true if the global allocator was initialized with `DebugAlloc`
+pub fn debug_checks() -> bool {
+    DEBUG_CHECKS.load(Ordering::Relaxed)
+}
+
+/// Panics if any of the `len` bytes at `ptr` were written to since they were freed
+unsafe fn check_sentinel(ptr: *const u8, len: usize) {
+    if let Some(i) = (0..len).find(|&i| *ptr.add(i) != UNALLOCATED_DATA_SENTINEL) {
+        panic!(
+            "use after free: {:?} was written to after it was freed",
+            ptr.add(i)
+        );
+    }
+}
+
+/// The most return addresses recorded for a tracked allocation
+const N_TRACKED_FRAMES: usize = 16;
+
+/// A live allocation, recorded with `RuntimeOptions::with_leak_tracking` or in debug mode
+#[derive(Debug, Clone, Copy)]
+struct TrackedAllocation {
+    /// Null for free slots and `REMOVED` for the slots of freed allocations
+    ptr: *mut u8,
+    size: usize,
+    tid: u32,
+    /// Return addresses, innermost first, padded with zeroes. Only recorded with leak tracking.
+    frames: [usize; N_TRACKED_FRAMES],
+}
+
+const REMOVED: *mut u8 = usize::MAX as *mut u8;
+
+/// Live allocations by address, in an open addressing hash table in its own mapping
+struct AllocationTable {
+    slots: *mut TrackedAllocation,
+    /// Always a power of two
+    capacity: usize,
+    /// Slots that are not free, including removed ones
+    n_used: usize,
+    len: usize,
+}
+
+impl AllocationTable {
+    const fn new() -> Self {
+        Self {
+            slots: null_mut(),
+            capacity: 0,
+            n_used: 0,
+            len: 0,
+        }
+    }
+
+    fn first_slot(&self, ptr: *mut u8) -> usize {
+        // Fibonacci hashing; the low bits of `ptr` are mostly zero
+        let shift = size_of::<usize>() * 8 - self.capacity.trailing_zeros() as usize;
+
+        (ptr as usize).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> shift
+    }
+
+    unsafe fn insert(&mut self, allocation: TrackedAllocation) -> SyscallResult<()> {
+        if (self.n_used + 1) * 2 > self.capacity {
+            self.grow()?;
+        }
+
+        let mut i = self.first_slot(allocation.ptr);
+
+        loop {
+            let slot = &mut *self.slots.add(i);
+
+            if slot.ptr.is_null() || slot.ptr == REMOVED {
+                if slot.ptr.is_null() {
+                    self.n_used += 1;
+                }
+
+                *slot = allocation;
+                self.len += 1;
+
+                return Ok(());
+            }
+
+            i = (i + 1) & (self.capacity - 1);
+        }
+    }
+
+    /// Returns the size of the removed allocation, if `ptr` was in the table
+    unsafe fn remove(&mut self, ptr: *mut u8) -> Option<usize> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        let mut i = self.first_slot(ptr);
+
+        loop {
+            let slot = &mut *self.slots.add(i);
+
+            if slot.ptr.is_null() {
+                return None;
+            }
+
+            if slot.ptr == ptr {
+                slot.ptr = REMOVED;
+                self.len -= 1;
+
+                return Some(slot.size);
+            }
+
+            i = (i + 1) & (self.capacity - 1);
+        }
+    }
+
+    /// Move the allocations into a new mapping, dropping removed slots
+    unsafe fn grow(&mut self) -> SyscallResult<()> {
+        let capacity = (4 * self.len)
+            .max(PAGESIZE / size_of::<TrackedAllocation>())
+            .next_power_of_two();
+
+        let slots = syscalls::mmap(
+            null_mut(),
+            capacity * size_of::<TrackedAllocation>(),
+            MProt::READ | MProt::WRITE,
+            MMapFlags::ANONYMOUS | MMapFlags::PRIVATE,
+            -1,
+            0,
+        )? as *mut TrackedAllocation;
+
+        let old = core::mem::replace(
+            self,
+            Self {
+                slots,
+                capacity,
+                n_used: 0,
+                len: 0,
+            },
+        );
+
+        for i in 0..old.capacity {
+            let slot = *old.slots.add(i);
+
+            if !slot.ptr.is_null() && slot.ptr != REMOVED {
+                self.insert(slot)?;
+            }
+        }
+
+        if !old.slots.is_null() {
+            syscalls::munmap(
+                old.slots as *mut u8,
+                old.capacity * size_of::<TrackedAllocation>(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Move the live allocations to the front of the mapping and return them,
+    /// leaving the table empty. The mapping is never freed.
+    unsafe fn take_live<'t>(&mut self) -> &'t mut [TrackedAllocation] {
+        let table = core::mem::replace(self, Self::new());
+
+        let mut n = 0;
+
+        for i in 0..table.capacity {
+            let slot = *table.slots.add(i);
+
+            if !slot.ptr.is_null() && slot.ptr != REMOVED {
+                *table.slots.add(n) = slot;
+                n += 1;
+            }
+        }
+
+        debug_assert_eq!(n, table.len);
+
+        if n == 0 {
+            return &mut [];
+        }
+
+        core::slice::from_raw_parts_mut(table.slots, n)
+    }
+}
+
+static LEAK_TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// NOTE: Owns the process `brk`
+/// => must be instanciated exactly once
+struct AllocatorInner {
+    backend: HeapBackend,
+    base: *mut Block,
+    /// The end of the heap. Only the actual `brk` for `HeapBackend::Brk`
+    brk: *mut Block,
+
+    // freed a block since the last time memory was
+    // attempted to be returned to the kernel
+    freed: bool,
+
+    /// The `BlockLinks` of all blocks, in their own mapping
+    links: *mut BlockLinks,
+    /// The number of `BlockLinks` the mapping has space for
+    links_capacity: usize,
+    /// The first empty block. Blocks whose memory was returned to the kernel
+    /// are at the end, so that the ones still in memory are used first.
+    empty: u32,
+    /// The last empty block
+    empty_tail: u32,
+    n_empty: usize,
+    /// The number of empty blocks whose memory was returned to the kernel
+    n_released: usize,
+    /// The first block with free chunks, for each chunk size
+    partial: [u32; MAX_CHUNK_SHIFT + 1],
+    /// The number of blocks that are not empty, for each chunk size
+    n_blocks: [usize; MAX_CHUNK_SHIFT + 1],
+
+    /// Bytes in chunks that are in use
+    chunk_bytes: usize,
+    mmap_allocations: usize,
+    mmap_bytes: usize,
+    peak_bytes: usize,
+
+    /// A ring buffer of `DebugAlloc::quarantine` allocations, in their own mapping
+    quarantine: *mut Quarantined,
+    quarantine_capacity: usize,
+    quarantine_start: usize,
+    quarantine_len: usize,
+
+    /// Live allocations, if leak tracking or debug mode is enabled
+    tracked: AllocationTable,
+}
+
+unsafe impl Send for AllocatorInner {}
+unsafe impl Sync for AllocatorInner {}
+
+struct Allocator(MaybeUninit<Mutex<AllocatorInner>>);
+
+unsafe impl Send for Allocator {}
+unsafe impl Sync for Allocator {}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let res = self.alloc(layout);
+
+        debug_assert_eq!(res.align_offset(layout.align()), 0);
+
+        self.track(res, layout.size());
+
+        res
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        // untrack first, another thread might get `ptr` as soon as it is freed
+        self.untrack(ptr, layout);
+
+        self.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let res = self.alloc(layout);
+
+        self.track(res, layout.size());
+
+        // fresh anonymous mappings are already zeroed
+        if !res.is_null() && !uses_mmap(&layout) {
+            res.write_bytes(0, layout.size());
+        }
+
+        res
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.untrack(ptr, layout);
+
+        let res = self.realloc(ptr, layout, new_size);
+
+        debug_assert_eq!(res.align_offset(layout.align()), 0);
+
+        // `ptr` is still allocated if this failed
+        if res.is_null() {
+            self.track(ptr, layout.size());
+        } else {
+            self.track(res, new_size);
+        }
+
+        res
+    }
+}
+
+/*
+const CHUNK_SHIFT: usize = 3;
+const CHUNK_SIZE: usize = 1 << CHUNK_SHIFT;
+const BLOCK_N_CHUNKS: usize = BLOCK_SIZE / CHUNK_SIZE;
+const BLOCK_FREE_MASK_SIZE: usize = BLOCK_N_CHUNKS / 8;
+const CHUNK_ALIGN: usize = if ALLOCATOR_ALIGN < CHUNK_SIZE {
+    ALLOCATOR_ALIGN
+} else {
+    CHUNK_SIZE
+};
+ */
+
+#[global_allocator]
+static mut GLOBAL_ALLOCATOR: Allocator = Allocator(MaybeUninit::uninit());
+
+/// Initializes the internal state of the global allocator
+/// *must* be called before *any* allocations are made (probably in _start)
+/// *must* be called exactly once
+pub unsafe fn init(
+    backend: HeapBackend,
+    debug: Option<DebugAlloc>,
+    leak_tracking: bool,
+    huge_pages: HugePages,
+) -> SyscallResult<()> {
+    HUGE_PAGES = huge_pages;
+
+    let base = match backend {
+        HeapBackend::Brk => {
+            let mut base = syscalls::brk(core::ptr::null())?;
+
+            let align_offset = base.align_offset(ALLOCATOR_ALIGN);
+
+            // Align base to `ALLOCATOR_ALIGN`
+            let new_base = base.add(align_offset);
+            if new_base != base {
+                base = new_base;
+                syscalls::brk(base)?;
+            }
+
+            base as *mut u8
+        }
+        // Blocks are made accessible when the heap grows into them
+        HeapBackend::Mmap => {
+            let base = syscalls::mmap(
+                null_mut(),
+                MMAP_HEAP_RESERVATION,
+                MProt::NONE,
+                MMapFlags::ANONYMOUS | MMapFlags::PRIVATE | MMapFlags::NORESERVE,
+                -1,
+                0,
+            )?;
+
+            advise_huge_pages(base, MMAP_HEAP_RESERVATION);
+
+            base
+        }
+    };
+
+    let base = base as *mut Block;
+
+    let quarantine_capacity = debug.map(|debug| debug.quarantine).unwrap_or(0);
+
+    let quarantine = if quarantine_capacity > 0 {
+        syscalls::mmap(
+            null_mut(),
+            quarantine_capacity * size_of::<Quarantined>(),
+            MProt::READ | MProt::WRITE,
+            MMapFlags::ANONYMOUS | MMapFlags::PRIVATE,
+            -1,
+            0,
+        )? as *mut Quarantined
+    } else {
+        null_mut()
+    };
+
+    DEBUG_CHECKS.store(debug.is_some(), Ordering::Relaxed);
+    LEAK_TRACKING.store(leak_tracking, Ordering::Relaxed);
+
+    // Safety: This Mutex is contained in a static and thus `Pin`ed
+    GLOBAL_ALLOCATOR = Allocator(MaybeUninit::new(FutexMutex::new(AllocatorInner {
+        backend,
+        base,
+        brk: base,
+        freed: false,
+        links: null_mut(),
+        links_capacity: 0,
+        empty: NO_BLOCK,
+        empty_tail: NO_BLOCK,
+        n_empty: 0,
+        n_released: 0,
+        partial: [NO_BLOCK; MAX_CHUNK_SHIFT + 1],
+        n_blocks: [0; MAX_CHUNK_SHIFT + 1],
+        chunk_bytes: 0,
+        mmap_allocations: 0,
+        mmap_bytes: 0,
+        peak_bytes: 0,
+        quarantine,
+        quarantine_capacity,
+        quarantine_start: 0,
+        quarantine_len: 0,
+        tracked: AllocationTable::new(),
+    })));
+
+    Ok(())
+}
+
+/// A snapshot of the memory use of the global allocator
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorStats {
+    /// Bytes in chunks that are in use. Chunks held by per-thread caches count as used.
+    pub bytes_allocated: usize,
+    /// The number of blocks that are not empty, by chunk shift:
+    /// `blocks_in_use[4]` counts the blocks of 16 byte chunks
+    pub blocks_in_use: [usize; MAX_CHUNK_SHIFT + 1],
+    /// Bytes taken up by the headers of the blocks in use
+    pub header_bytes: usize,
+    /// Allocations of at least `MMAP_THRESHOLD` bytes or aligned to a page,
+    /// which get their own mapping
+    pub mmap_allocations: usize,
+    pub mmap_bytes: usize,
+    /// The most bytes that were in use by chunks and mappings at once
+    pub peak_bytes: usize,
+}
+
+/// Collect statistics about the memory use of the global allocator.
+/// Walks all blocks, so this is not meant to be called in hot paths.
+pub fn stats() -> AllocatorStats {
+    // Safety: only written before `main`
+    assert!(
+        unsafe { RUNTIME_OPTIONS.alloc },
+        "the global allocator is disabled"
+    );
+
+    unsafe { GLOBAL_ALLOCATOR.lock().stats() }
+}
+
+const fn ordinal_s(x: usize) -> &'static str {
+    if x == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// De-initializes the global allocator
+/// at the moment this just frees all freeable blocks
+/// and prints how much memory was leaked (if any)
+pub unsafe fn deinit() -> SyscallResult<()> {
+    let mut inner = GLOBAL_ALLOCATOR.lock();
+
+    // Quarantined allocations are not leaked
+    while inner.quarantine_len > 0 {
+        inner.release_quarantined();
+    }
+
+    inner.try_return_mem()?;
+
+    let leaked_blocks = inner.brk.offset_from(inner.base);
+
+    if leaked_blocks != 0 {
+        warn!("LEAKED MEMORY:");
+
+        let n_bytes = inner.n_bytes_allocated();
+
+        if n_bytes == 0 {
+            panic!(
+                "allocator logic is wrong: there are allocated blocks left, but no allocated bytes"
+            );
+        }
+
+        warn!(
+            "program lost {} byte{} during its lifetime, keeping {} block{} ({} bytes) allocated",
+            n_bytes,
+            ordinal_s(n_bytes),
+            leaked_blocks,
+            ordinal_s(leaked_blocks as usize),
+            leaked_blocks as usize * BLOCK_SIZE,
+        );
+    }
+
+    if LEAK_TRACKING.swap(false, Ordering::Relaxed) {
+        report_leaks(inner.tracked.take_live());
+    }
+
+    Ok(())
+}
+
+/// Print the allocations that were never freed, grouped by their call stack
+fn report_leaks(leaked: &mut [TrackedAllocation]) {
+    if leaked.is_empty() {
+        return;
+    }
+
+    leaked.sort_unstable_by_key(|allocation| allocation.frames);
+
+    warn!(
+        "{} allocation{} never freed. Addresses are relative to the executable, see `addr2line -e <executable>`:",
+        leaked.len(),
+        if leaked.len() == 1 { " was" } else { "s were" },
+    );
+
+    let base = backtrace::executable_base();
+
+    let mut rest = &*leaked;
+
+    while let Some(first) = rest.first() {
+        let n = rest
+            .iter()
+            .take_while(|allocation| allocation.frames == first.frames)
+            .count();
+
+        let (site, tail) = rest.split_at(n);
+        rest = tail;
+
+        let n_bytes: usize = site.iter().map(|allocation| allocation.size).sum();
+
+        warn!(
+            "{} allocation{} ({} byte{}), first by thread [{}]:",
+            site.len(),
+            ordinal_s(site.len()),
+            n_bytes,
+            ordinal_s(n_bytes),
+            site[0].tid,
+        );
+
+        for (i, &frame) in site[0]
+            .frames
+            .iter()
+            .take_while(|&&frame| frame != 0)
+            .enumerate()
+        {
+            warn!("    #{:<2} {:#x}", i, frame.wrapping_sub(base));
+        }
+    }
+}
+
+impl AllocatorInner {
+    unsafe fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats {
+            mmap_allocations: self.mmap_allocations,
+            mmap_bytes: self.mmap_bytes,
+            peak_bytes: self.peak_bytes,
+            ..AllocatorStats::default()
+        };
+
+        let n = self.brk.offset_from(self.base) as usize;
+
+        for i in 0..n {
+            let block = &*self.base.add(i);
+
+            if block.is_empty() {
+                continue;
+            }
+
+            stats.bytes_allocated += block.n_bytes_allocated();
+            stats.blocks_in_use[block.chunk_shift() as usize] += 1;
+            stats.header_bytes += block.n_header_chunks() * block.chunk_size();
+        }
+
+        debug_assert_eq!(stats.bytes_allocated, self.chunk_bytes);
+
+        stats
+    }
+
+    fn update_peak(&mut self) {
+        self.peak_bytes = self.peak_bytes.max(self.chunk_bytes + self.mmap_bytes);
+    }
+
+    pub unsafe fn n_bytes_allocated(&self) -> usize {
+        let mut n_bytes = 0;
+
+        let n = self.brk.offset_from(self.base) as usize;
+
+        for i in 0..n {
+            let block_ptr = self.base.add(i);
+
+            let block = &mut *block_ptr;
+
+            n_bytes += block.n_bytes_allocated();
+        }
+
+        n_bytes
+    }
+
+    unsafe fn resize_brk(&mut self, n: isize) -> SyscallResult<()> {
+        let n_blocks = self.brk.offset_from(self.base);
+
+        trace!(
+            "{}\x1b[m brk by {} block{} ({} bytes): {} -> {}",
+            if n > 0 {
+                "\x1b[32mGrowing"
+            } else {
+                "\x1b[33mShrinking"
+            },
+            n.abs(),
+            ordinal_s(n.abs() as usize),
+            n.abs() as usize * BLOCK_SIZE,
+            n_blocks,
+            n_blocks + n
+        );
+
+        // dbg!(self.n_bytes_allocated());
+
+        if n > 0 {
+            self.reserve_links((n_blocks + n) as usize)?;
+        }
+
+        let new_brk = self.brk.offset(n);
+
+        match self.backend {
+            HeapBackend::Brk => {
+                self.brk = syscalls::brk(new_brk as *const u8)? as *mut Block;
+            }
+            HeapBackend::Mmap => {
+                let size = n.unsigned_abs() * BLOCK_SIZE;
+
+                let heap_size = new_brk.offset_from(self.base) as usize * BLOCK_SIZE;
+
+                if n > 0 {
+                    if heap_size > MMAP_HEAP_RESERVATION {
+                        return Err(SyscallError(SyscallErrorKind::ENOMEM as u32));
+                    }
+
+                    syscalls::mprotect(self.brk as *mut u8, size, MProt::READ | MProt::WRITE)?;
+                } else {
+                    syscalls::madvise(new_brk as *mut u8, size, MAdvice::DontNeed)?;
+                    syscalls::mprotect(new_brk as *mut u8, size, MProt::NONE)?;
+                }
+
+                self.brk = new_brk;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make space for the `BlockLinks` of `n_blocks` blocks
+    unsafe fn reserve_links(&mut self, n_blocks: usize) -> SyscallResult<()> {
+        if n_blocks <= self.links_capacity {
+            return Ok(());
+        }
+
+        let capacity = n_blocks
+            .max(2 * self.links_capacity)
+            .max(PAGESIZE / size_of::<BlockLinks>());
+
+        let old_size = self.links_capacity * size_of::<BlockLinks>();
+        let new_size = capacity * size_of::<BlockLinks>();
+
+        let links = if self.links.is_null() {
+            syscalls::mmap(
+                null_mut(),
+                new_size,
+                MProt::READ | MProt::WRITE,
+                MMapFlags::ANONYMOUS | MMapFlags::PRIVATE,
+                -1,
+                0,
+            )?
+        } else {
+            syscalls::mremap(
+                self.links as *mut u8,
+                old_size,
+                new_size,
+                MRemapFlags::MAYMOVE,
+            )?
+        };
+
+        self.links = links as *mut BlockLinks;
+        self.links_capacity = capacity;
+
+        Ok(())
+    }
+
+    fn head(&mut self, list: BlockList) -> &mut u32 {
+        match list {
+            BlockList::Empty => &mut self.empty,
+            BlockList::Partial(shift) => &mut self.partial[shift],
+        }
+    }
+
+    unsafe fn link(&mut self, index: u32, list: BlockList) {
+        let head = *self.head(list);
+
+        *self.links.add(index as usize) = BlockLinks {
+            prev: NO_BLOCK,
+            next: head,
+            released: false,
+        };
+
+        if head != NO_BLOCK {
+            (*self.links.add(head as usize)).prev = index;
+        }
+
+        *self.head(list) = index;
+
+        if list == BlockList::Empty {
+            if head == NO_BLOCK {
+                self.empty_tail = index;
+            }
+
+            self.n_empty += 1;
+        }
+    }
+
+    /// Link an empty block whose memory was returned to the kernel,
+    /// at the end of the empty list
+    unsafe fn link_released(&mut self, index: u32) {
+        let tail = self.empty_tail;
+
+        *self.links.add(index as usize) = BlockLinks {
+            prev: tail,
+            next: NO_BLOCK,
+            released: true,
+        };
+
+        if tail == NO_BLOCK {
+            self.empty = index;
+        } else {
+            (*self.links.add(tail as usize)).next = index;
+        }
+
+        self.empty_tail = index;
+
+        self.n_empty += 1;
+        self.n_released += 1;
+    }
+
+    unsafe fn unlink(&mut self, index: u32, list: BlockList) {
+        let BlockLinks {
+            prev,
+            next,
+            released,
+        } = *self.links.add(index as usize);
+
+        if prev == NO_BLOCK {
+            *self.head(list) = next;
+        } else {
+            (*self.links.add(prev as usize)).next = next;
+        }
+
+        if next != NO_BLOCK {
+            (*self.links.add(next as usize)).prev = prev;
+        } else if list == BlockList::Empty {
+            self.empty_tail = prev;
+        }
+
+        if list == BlockList::Empty {
+            self.n_empty -= 1;
+
+            if released {
+                self.n_released -= 1;
+            }
+        }
+    }
+
+    /// Run `f` on the block at `index`, then move the block to the list matching its new state
+    unsafe fn update_block<R>(&mut self, index: u32, f: impl FnOnce(&mut Block) -> R) -> R {
+        let block = &mut *self.base.add(index as usize);
+
+        let old_shift = block.chunk_shift() as usize;
+        let old_list = block.list();
+
+        let res = f(block);
+
+        let new_shift = block.chunk_shift() as usize;
+        let new_list = block.list();
+
+        if old_list != new_list {
+            if let Some(list) = old_list {
+                self.unlink(index, list);
+            }
+
+            if let Some(list) = new_list {
+                self.link(index, list);
+            }
+
+            if old_list == Some(BlockList::Empty) {
+                self.n_blocks[new_shift] += 1;
+            } else if new_list == Some(BlockList::Empty) {
+                self.n_blocks[old_shift] -= 1;
+            }
+        }
+
+        res
+    }
+
+    pub unsafe fn try_return_mem(&mut self) -> SyscallResult<()> {
+        if self.freed {
+            // trace!("attempting to return memory to kernel");
+
+            let last_block = self.brk.sub(1);
+
+            let mut block_ptr = last_block;
+
+            while self.base.offset_from(block_ptr) <= 0 && (&*block_ptr).is_empty() {
+                self.unlink(block_ptr.offset_from(self.base) as u32, BlockList::Empty);
+
+                block_ptr = block_ptr.sub(1);
+            }
+
+            let brk_offset = block_ptr.offset_from(self.brk) + 1;
+
+            if brk_offset != 0 {
+                self.resize_brk(brk_offset)?;
+            }
+
+            self.freed = false;
+        }
+
+        Ok(())
+    }
+
+    unsafe fn free_in_blocks(&mut self, ptr: *mut u8, size: usize) {
+        let offset = ptr.offset_from(self.base as *mut u8);
+
+        if offset < 0 || ptr.offset_from(self.brk as *mut u8) >= 0 {
+            panic!("tried to free a pointer not inside of the `brk`: {:?}", ptr);
+        }
+
+        let offset = offset as usize;
+
+        let block_index = offset / BLOCK_SIZE;
+        let offset_in_block = offset % BLOCK_SIZE;
+
+        // dbg!(block_index, offset_in_block);
+
+        let chunk_shift = (*self.base.add(block_index)).chunk_shift() as u32;
+        self.chunk_bytes -= ceil_shr(size, chunk_shift) << chunk_shift;
+
+        let now_empty = self.update_block(block_index as u32, |block| {
+            block.free(offset_in_block, size);
+
+            block.is_empty()
+        });
+
+        // Blocks at the top are returned by `try_return_mem`.
+        // Returned blocks lose their sentinel, so debug mode keeps them.
+        if now_empty
+            && self.backend == HeapBackend::Mmap
+            && self.n_empty - self.n_released > N_KEEP_EMPTY_BLOCKS
+            && !debug_checks()
+        {
+            // The block reads as zeroes afterwards, which is an empty block of 1 byte chunks
+            syscalls::madvise(
+                self.base.add(block_index) as *mut u8,
+                BLOCK_SIZE,
+                MAdvice::DontNeed,
+            )
+            .expect("Failed to return memory to kernel");
+
+            // Move it behind the empty blocks that are still in memory
+            self.unlink(block_index as u32, BlockList::Empty);
+            self.link_released(block_index as u32);
+        }
+
+        self.freed = true;
+    }
+
+    /// Allocate `layout` in a block, creating new blocks if needed
+    unsafe fn alloc_in_blocks(&mut self, layout: Layout) -> *mut u8 {
+        let chunk_size = prefered_chunk_size(&layout);
+
+        let shift = size_of::<usize>() * 8 - chunk_size.leading_zeros() as usize - 1;
+
+        debug_assert_eq!(1 << shift, chunk_size);
+
+        // `uses_mmap` keeps both the size and the alignment of block allocations small
+        debug_assert!(shift <= MMAP_THRESHOLD_SHIFT);
+
+        let used_bytes = ceil_shr(layout.size(), shift as u32) << shift;
+
+        // Allocations of a single chunk always fit into the first block
+        let mut index = self.partial[shift];
+        while index != NO_BLOCK {
+            let next = (*self.links.add(index as usize)).next;
+
+            if let Some(offset) = self.update_block(index, |block| block.alloc(layout.size())) {
+                self.chunk_bytes += used_bytes;
+                self.update_peak();
+
+                let ptr = (self.base.add(index as usize) as *mut u8).add(offset);
+
+                if debug_checks() {
+                    check_sentinel(ptr, used_bytes);
+                }
+
+                return ptr;
+            }
+
+            index = next;
+        }
+
+        if self.empty == NO_BLOCK {
+            let n_new_blocks = (self.n_blocks[shift] >> 1).max(1);
+
+            if self.alloc_blocks(n_new_blocks, shift).is_err() {
+                return null_mut();
+            }
+        }
+
+        let index = self.empty;
+        let block_ptr = self.base.add(index as usize);
+
+        let offset = self.update_block(index, |block| {
+            if block.chunk_shift() as usize != shift {
+                trace!("reusing existing empty chunk \x1b[34m#{}\x1b[m", index);
+
+                if debug_checks() {
+                    let data = block.data();
+                    check_sentinel(data.as_ptr(), data.len());
+                }
+
+                *block = Block::new(shift);
+            }
+
+            block.alloc(layout.size())
+        });
+
+        let offset = offset.expect("allocation does not fit into an empty block");
+
+        self.chunk_bytes += used_bytes;
+        self.update_peak();
+
+        let ptr = (block_ptr as *mut u8).add(offset);
+
+        if debug_checks() {
+            check_sentinel(ptr, used_bytes);
+        }
+
+        ptr
+    }
+
+    /// Panics if `ptr` is not a live allocation of `layout`
+    unsafe fn check_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let offset = ptr.offset_from(self.base as *mut u8);
+        let in_heap = offset >= 0 && ptr.offset_from(self.brk as *mut u8) < 0;
+
+        if ptr.align_offset(layout.align()) != 0 {
+            panic!("freed {:?} with {:?}, but it is not aligned", ptr, layout);
+        }
+
+        if uses_mmap(&layout) {
+            if in_heap {
+                panic!(
+                    "freed {:?} with {:?}, but it was allocated in a block",
+                    ptr, layout
+                );
+            }
+
+            let allocation = (ptr.sub(size_of::<*mut u8>()) as *const *mut u8).read_unaligned();
+
+            let data_ptr = allocation.add(size_of::<*mut u8>());
+
+            if allocation as usize % PAGESIZE != 0
+                || data_ptr.add(data_ptr.align_offset(layout.align())) != ptr
+            {
+                panic!(
+                    "freed {:?} with {:?}, but it is not the start of a mapping",
+                    ptr, layout
+                );
+            }
+
+            return;
+        }
+
+        if !in_heap {
+            panic!("tried to free a pointer not inside of the `brk`: {:?}", ptr);
+        }
+
+        let offset = offset as usize;
+        let block = &*self.base.add(offset / BLOCK_SIZE);
+
+        let offset_in_block = offset % BLOCK_SIZE;
+        let chunk_size = block.chunk_size();
+
+        if offset_in_block % chunk_size != 0 || offset_in_block < block.first_chunk() * chunk_size {
+            panic!("freed {:?}, which is not the start of a chunk", ptr);
+        }
+
+        let start_chunk = offset_in_block / chunk_size - block.n_header_chunks();
+
+        if block.is_chunk_free(start_chunk) || self.is_quarantined(ptr) {
+            panic!("double free of {:?}", ptr);
+        }
+
+        // Allocations always live in blocks of their prefered chunk size
+        if chunk_size != prefered_chunk_size(&layout) {
+            panic!(
+                "freed {:?} with {:?}, but it was allocated in {} byte chunks",
+                ptr, layout, chunk_size
+            );
+        }
+
+        let n_chunks = ceil_shr(layout.size(), chunk_size.trailing_zeros());
+
+        if (start_chunk..start_chunk + n_chunks).any(|i| block.is_chunk_free(i)) {
+            panic!(
+                "freed {:?} with {:?}, which is larger than the allocation",
+                ptr, layout
+            );
+        }
+    }
+
+    fn is_quarantined(&self, ptr: *mut u8) -> bool {
+        (0..self.quarantine_len).any(|i| unsafe {
+            let index = (self.quarantine_start + i) % self.quarantine_capacity;
+
+            (*self.quarantine.add(index)).ptr == ptr
+        })
+    }
+
+    /// Free the allocation at `ptr` once it is the oldest of the quarantine
+    unsafe fn quarantine(&mut self, ptr: *mut u8, size: usize) {
+        if self.quarantine_capacity == 0 {
+            self.free_in_blocks(ptr, size);
+            return;
+        }
+
+        if self.quarantine_len == self.quarantine_capacity {
+            self.release_quarantined();
+        }
+
+        let index = (self.quarantine_start + self.quarantine_len) % self.quarantine_capacity;
+
+        *self.quarantine.add(index) = Quarantined { ptr, size };
+        self.quarantine_len += 1;
+    }
+
+    /// Free the oldest allocation of the quarantine
+    unsafe fn release_quarantined(&mut self) {
+        let Quarantined { ptr, size } = *self.quarantine.add(self.quarantine_start);
+
+        self.quarantine_start = (self.quarantine_start + 1) % self.quarantine_capacity;
+        self.quarantine_len -= 1;
+
+        check_sentinel(ptr, size);
+
+        self.free_in_blocks(ptr, size);
+    }
+
+    /// Grow the `brk` by `n` empty blocks
+    unsafe fn alloc_blocks(&mut self, n: usize, chunk_shift: usize) -> SyscallResult<()> {
+        let new_block = Block::new(chunk_shift);
+
+        let block_ptr = self.brk;
+
+        self.resize_brk(n as isize)?;
+
+        let first_index = block_ptr.offset_from(self.base) as u32;
+
+        // link them in reverse, so that the lowest block is used first
+        for i in (0..n).rev() {
+            *block_ptr.add(i) = new_block;
+
+            self.link(first_index + i as u32, BlockList::Empty);
+        }
+
+        {
+            let created_block = &*block_ptr;
+
+            let n_chunks = created_block.n_chunks();
+            let n_useable = n_chunks - created_block.n_header_chunks();
+
+            let loss = 100. * (1.0 - n_useable as f64 / n_chunks as f64);
+
+            trace!(
+                "Allocated {} block{} with {} ({} useable | \x1b[{}m{:.1}%\x1b[m loss) {} byte chunk{}, aligned to {} bits",
+                n,
+                ordinal_s(n),
+                n_chunks,
+                n_useable,
+                if loss < 5. {
+                    "32"
+                } else if loss < 10. {
+                    "33"
+                } else {
+                    "31"
+                },
+                loss,
+                created_block.chunk_size(),
+                ordinal_s(created_block.n_chunks()),
+                created_block.align(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The chunk size of the blocks `layout` is allocated in.
+/// Chunks are aligned to their size, so this is at least `layout.align()`.
+pub fn prefered_chunk_size(layout: &Layout) -> usize {
+    let n = layout.size() / layout.align();
+
+    (n * layout.align())
+        .next_power_of_two()
+        .max(layout.align())
+        .max(1 << MIN_CHUNK_SHIFT)
+}
+
+/// Allocations that get their own mapping instead of going into a block:
+/// large ones, and ones aligned to a page or more
+fn uses_mmap(layout: &Layout) -> bool {
+    layout.size() >= MMAP_THRESHOLD || layout.align() >= PAGESIZE
+}
+
+/// Mappings that `HugePages::HugeTlb` tries to back with huge pages
+fn uses_hugetlb(mapping_size: usize) -> bool {
+    // Safety: only written before the first allocation
+    unsafe { HUGE_PAGES == HugePages::HugeTlb && mapping_size >= HUGE_PAGE_SIZE }
+}
+
+/// The size of the mapping of an allocation that `uses_mmap`.
+/// The data starts after a header and padding for the alignment.
+fn mapping_size(size: usize, align: usize) -> usize {
+    let mapping_size = size + align + size_of::<*mut u8>();
+
+    // Huge page mappings can only be unmapped in whole huge pages
+    if uses_hugetlb(mapping_size) {
+        (mapping_size + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1)
+    } else {
+        mapping_size
+    }
+}
+
+/// Ask for the mapping at `ptr` to be backed by transparent huge pages, if it is large enough
+unsafe fn advise_huge_pages(ptr: *mut u8, size: usize) {
+    // Safety: only written before the first allocation
+    if HUGE_PAGES != HugePages::Off && size >= HUGE_PAGE_SIZE {
+        // This fails if transparent huge pages are disabled, which only costs performance
+        let _ = syscalls::madvise(ptr, size, MAdvice::HugePage);
+    }
+}
+
+/// Map `size` bytes for an allocation that `uses_mmap`
+unsafe fn map_allocation(size: usize) -> SyscallResult<*mut u8> {
+    let prot = MProt::READ | MProt::WRITE;
+    let flags = MMapFlags::ANONYMOUS | MMapFlags::PRIVATE;
+
+    // This fails once the reserved huge pages run out
+    if uses_hugetlb(size) {
+        let huge_flags = flags | MMapFlags::HUGETLB | MMapFlags::HUGE_2MB;
+
+        if let Ok(res) = syscalls::mmap(null_mut(), size, prot, huge_flags, -1, 0) {
+            return Ok(res);
+        }
+    }
+
+    let res = syscalls::mmap(null_mut(), size, prot, flags, -1, 0)?;
+
+    advise_huge_pages(res, size);
+
+    Ok(res)
+}
+
+/// Size classes are cached for chunk sizes up to `MMAP_THRESHOLD`
+const N_CACHE_CLASSES: usize = MMAP_THRESHOLD_SHIFT + 1;
+const CACHE_SIZE: usize = 32;
+/// The number of chunks moved between a `ThreadCache` and the blocks at once
+const CACHE_BATCH: usize = CACHE_SIZE / 2;
+
+static THREAD_CACHES: AtomicBool = AtomicBool::new(true);
+
+/// Enable or disable the per-thread caches for new allocations.
+/// Chunks which are already cached stay in their cache.
+///
+/// The caches are only used if the CPU and kernel support `rdgsbase`
+/// (`FSGSBASE` in `AT_HWCAP2`), since finding them must not need a syscall,
+/// and never in debug mode. See `thread_caches_active`.
+pub fn set_thread_caches(enabled: bool) {
+    THREAD_CACHES.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if allocations currently go through the per-thread caches
+pub fn thread_caches_active() -> bool {
+    THREAD_CACHES.load(Ordering::Relaxed) && !debug_checks() && tls::has_fast_tls_ptr()
+}
+
+/// The size class `layout` is cached in, if it fits into a single chunk of
+/// its prefered size. All of these allocations end up in a block of that chunk
+/// size, whether they went through a cache or not.
+fn cache_class(layout: &Layout) -> Option<usize> {
+    if uses_mmap(layout) {
+        return None;
+    }
+
+    let chunk_size = prefered_chunk_size(layout);
+
+    if chunk_size < layout.size() {
+        return None;
+    }
+
+    Some(chunk_size.trailing_zeros() as usize)
+}
+
+/// The cache of the current thread, if it has one
+unsafe fn thread_cache<'t>() -> Option<&'t mut ThreadCache> {
+    // Chunks in a cache look allocated, which would hide double frees
+    if !thread_caches_active() {
+        return None;
+    }
+
+    tls::try_get_tls_ptr_fast().map(|tls| &mut (*tls).alloc_cache)
+}
+
+/// Free chunks owned by a single thread, so that most allocations
+/// don't need to take the allocator lock.
+/// Dropping it returns the chunks to their blocks.
+pub struct ThreadCache {
+    lens: [usize; N_CACHE_CLASSES],
+    chunks: [[*mut u8; CACHE_SIZE]; N_CACHE_CLASSES],
+}
+
+impl ThreadCache {
+    pub const fn new() -> Self {
+        Self {
+            lens: [0; N_CACHE_CLASSES],
+            chunks: [[null_mut(); CACHE_SIZE]; N_CACHE_CLASSES],
+        }
+    }
+
+    fn is_full(&self, class: usize) -> bool {
+        self.lens[class] == CACHE_SIZE
+    }
+
+    fn push(&mut self, class: usize, chunk: *mut u8) {
+        let len = &mut self.lens[class];
+
+        self.chunks[class][*len] = chunk;
+        *len += 1;
+    }
+
+    fn pop(&mut self, class: usize) -> Option<*mut u8> {
+        let len = &mut self.lens[class];
+
+        if *len == 0 {
+            return None;
+        }
+
+        *len -= 1;
+
+        Some(self.chunks[class][*len])
+    }
+}
+
+impl Default for ThreadCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for ThreadCache {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ThreadCache")
+            .field("lens", &self.lens)
+            .finish()
+    }
+}
+
+impl Drop for ThreadCache {
+    fn drop(&mut self) {
+        if self.lens.iter().all(|&len| len == 0) {
+            return;
+        }
+
+        // Safety: there are cached chunks, so the allocator was initialized
+        let mut inner = unsafe { GLOBAL_ALLOCATOR.lock() };
+
+        for class in 0..N_CACHE_CLASSES {
+            while let Some(chunk) = self.pop(class) {
+                unsafe { inner.free_in_blocks(chunk, 1 << class) };
+            }
+        }
+    }
+}
+
+impl Allocator {
+    unsafe fn lock(&self) -> FutexMutexGuard<'_, AllocatorInner> {
+        self.0.assume_init_ref().lock()
+    }
+
+    /// Record the allocation at `ptr`, and where it came from if leak tracking is enabled.
+    /// Debug mode records the size, so that frees with the wrong size are caught.
+    unsafe fn track(&self, ptr: *mut u8, size: usize) {
+        let leak_tracking = LEAK_TRACKING.load(Ordering::Relaxed);
+
+        if ptr.is_null() || !(leak_tracking || debug_checks()) {
+            return;
+        }
+
+        let mut allocation = TrackedAllocation {
+            ptr,
+            size,
+            tid: syscalls::gettid(),
+            frames: [0; N_TRACKED_FRAMES],
+        };
+
+        if leak_tracking {
+            backtrace::return_addresses(&mut allocation.frames);
+        }
+
+        // Allocations are only missing from the report if the table can't grow
+        let _ = self.lock().tracked.insert(allocation);
+    }
+
+    /// Forget the allocation at `ptr`, which is freed with `layout`
+    unsafe fn untrack(&self, ptr: *mut u8, layout: Layout) {
+        if !(LEAK_TRACKING.load(Ordering::Relaxed) || debug_checks()) {
+            return;
+        }
+
+        let size = self.lock().tracked.remove(ptr);
+
+        // A layout that is too small would leave the rest of the allocation in use.
+        // Unknown pointers are left to `check_dealloc`.
+        if let Some(size) = size {
+            if debug_checks() && size != layout.size() {
+                panic!(
+                    "freed {:?} with {:?}, but it was allocated with {} bytes",
+                    ptr, layout, size
+                );
+            }
+        }
+    }
+
+    // TODO: skip bytes which are 0
+    // optimise asm
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        // trace!("alloc: {:?}", layout);
+
+        if let Some(class) = cache_class(&layout) {
+            if let Some(cache) = thread_cache() {
+                return match cache.pop(class) {
+                    Some(ptr) => ptr,
+                    None => self.refill(cache, class),
+                };
+            }
+        }
+
+        let mut inner = self.lock();
+
+        if uses_mmap(&layout) {
+            let allocation_size = mapping_size(layout.size(), layout.align());
+
+            let allocation = if let Ok(res) = map_allocation(allocation_size) {
+                res
+            } else {
+                return null_mut();
+            };
+
+            let data_ptr = allocation.add(size_of::<*mut u8>());
+            let align_offset = data_ptr.align_offset(layout.align());
+
+            // write start of allocation just before the data pointer
+            let header_ptr = allocation.add(align_offset) as *mut *mut u8;
+            header_ptr.write_unaligned(allocation);
+
+            inner.mmap_allocations += 1;
+            inner.mmap_bytes += allocation_size;
+            inner.update_peak();
+
+            return data_ptr.add(align_offset);
+        }
+
+        let res = inner.alloc_in_blocks(layout);
+
+        inner
+            .try_return_mem()
+            .expect("Failed to return memory to kernel");
+
+        res
+    }
+
+    /// Move a batch of chunks of `class` into `cache` and return one of them
+    unsafe fn refill(&self, cache: &mut ThreadCache, class: usize) -> *mut u8 {
+        let chunk_size = 1 << class;
+        let layout = Layout::from_size_align_unchecked(chunk_size, chunk_size.min(ALLOCATOR_ALIGN));
+
+        let mut inner = self.lock();
+
+        for _ in 0..CACHE_BATCH {
+            let ptr = inner.alloc_in_blocks(layout);
+
+            if ptr.is_null() {
+                break;
+            }
+
+            cache.push(class, ptr);
+        }
+
+        inner
+            .try_return_mem()
+            .expect("Failed to return memory to kernel");
+
+        cache.pop(class).unwrap_or(null_mut())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // trace!("realloc: {:?} with {:?} to {}", ptr, layout, new_size);
+
+        let old_size = layout.size();
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+
+        if debug_checks() {
+            self.lock().check_dealloc(ptr, layout);
+        }
+
+        if uses_mmap(&layout) && uses_mmap(&new_layout) {
+            // `mremap` keeps the offset into the page, so this only works
+            // if the alignment is not larger than a page.
+            // Mappings that might be huge pages are not resized, since they
+            // have to stay a multiple of `HUGE_PAGE_SIZE`.
+            if layout.align() <= PAGESIZE
+                && !uses_hugetlb(mapping_size(old_size, layout.align()))
+                && !uses_hugetlb(mapping_size(new_size, layout.align()))
+            {
+                return self.remap(ptr, layout, new_size);
+            }
+        } else if !uses_mmap(&layout) && !uses_mmap(&new_layout) {
+            if UNALLOCATED_DATA_SENTINEL != 0 && new_size < old_size {
+                ptr.add(new_size)
+                    .write_bytes(UNALLOCATED_DATA_SENTINEL, old_size - new_size);
+            }
+
+            let mut inner = self.lock();
+
+            let offset = ptr.offset_from(inner.base as *mut u8) as usize;
+
+            let index = (offset / BLOCK_SIZE) as u32;
+            // Copied out, since `update_block` mutates the block
+            let shift = (*inner.base.add(index as usize)).chunk_shift() as u32;
+            let chunk_size = 1 << shift;
+
+            // Allocations that can be cached must stay a single chunk of their class.
+            // Debug mode expects all allocations to be in blocks of their prefered chunk size.
+            let keeps_class = if debug_checks() {
+                chunk_size == prefered_chunk_size(&new_layout)
+            } else {
+                cache_class(&new_layout)
+                    .map(|class| chunk_size == 1 << class)
+                    .unwrap_or(true)
+            };
+
+            if keeps_class
+                && inner.update_block(index, |block| {
+                    block.resize(offset % BLOCK_SIZE, old_size, new_size)
+                })
+            {
+                if debug_checks() && new_size > old_size {
+                    check_sentinel(
+                        ptr.add(old_size),
+                        (ceil_shr(new_size, shift) << shift) - old_size,
+                    );
+                }
+
+                inner.chunk_bytes -= ceil_shr(old_size, shift) << shift;
+                inner.chunk_bytes += ceil_shr(new_size, shift) << shift;
+                inner.update_peak();
+
+                if new_size < old_size {
+                    inner.freed = true;
+                }
+
+                return ptr;
+            }
+        }
+
+        let res = self.alloc(new_layout);
+
+        if !res.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, res, old_size.min(new_size));
+
+            self.dealloc(ptr, layout);
+        }
+
+        res
+    }
+
+    /// Resize an allocation above `MMAP_THRESHOLD`, letting the kernel move it if needed
+    unsafe fn remap(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let old_allocation_size = mapping_size(layout.size(), layout.align());
+        let new_allocation_size = mapping_size(new_size, layout.align());
+
+        let header_ptr = ptr.sub(size_of::<*mut u8>()) as *mut *mut u8;
+        let allocation = header_ptr.read_unaligned();
+
+        let new_allocation = match syscalls::mremap(
+            allocation,
+            old_allocation_size,
+            new_allocation_size,
+            MRemapFlags::MAYMOVE,
+        ) {
+            Ok(res) => res,
+            Err(_) => return null_mut(),
+        };
+
+        if old_allocation_size < HUGE_PAGE_SIZE {
+            advise_huge_pages(new_allocation, new_allocation_size);
+        }
+
+        // Both mappings are page aligned, so the data is at the same offset
+        let res = new_allocation.offset(ptr.offset_from(allocation));
+
+        let header_ptr = res.sub(size_of::<*mut u8>()) as *mut *mut u8;
+        header_ptr.write_unaligned(new_allocation);
+
+        let mut inner = self.lock();
+        inner.mmap_bytes -= old_allocation_size;
+        inner.mmap_bytes += new_allocation_size;
+        inner.update_peak();
+
+        res
+    }
+
+    // TODO: do not leak all allocated memory..
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        // trace!("dealloc: {:?} with {:?}", ptr, layout);
+
+        if debug_checks() {
+            let mut inner = self.lock();
+
+            inner.check_dealloc(ptr, layout);
+
+            if !uses_mmap(&layout) {
+                ptr.write_bytes(UNALLOCATED_DATA_SENTINEL, layout.size());
+
+                inner.quarantine(ptr, layout.size());
+
+                return;
+            }
+        }
+
+        if uses_mmap(&layout) {
+            // debug!("munmap-ing");
+            // trace!("dealloc: {:?} with {:?}", ptr, layout);
+
+            let allocation_size = mapping_size(layout.size(), layout.align());
+
+            let allocation_ptr_ptr = ptr.sub(size_of::<*mut u8>()) as *const *mut u8;
+
+            let allocation_ptr = allocation_ptr_ptr.read_unaligned();
+
+            syscalls::munmap(allocation_ptr, allocation_size).expect("Failed to munmap memory");
+
+            let mut inner = self.lock();
+            inner.mmap_allocations -= 1;
+            inner.mmap_bytes -= allocation_size;
+
+            return;
+        }
+
+        if UNALLOCATED_DATA_SENTINEL != 0 {
+            for i in 0..layout.size() {
+                *ptr.add(i) = UNALLOCATED_DATA_SENTINEL;
+            }
+        }
+
+        if let Some(class) = cache_class(&layout) {
+            if let Some(cache) = thread_cache() {
+                if cache.is_full(class) {
+                    let mut inner = self.lock();
+
+                    for _ in 0..CACHE_BATCH {
+                        let chunk = cache.pop(class).unwrap();
+
+                        inner.free_in_blocks(chunk, 1 << class);
+                    }
+                }
+
+                cache.push(class, ptr);
+
+                return;
+            }
+        }
+
+        self.lock().free_in_blocks(ptr, layout.size());
+    }
+}
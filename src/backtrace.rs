@@ -0,0 +1,72 @@
+//! Stack traces collected by walking frame pointers.
+//! Frames of code built without frame pointers are skipped or end the trace,
+//! which is why `.cargo/config.toml` forces them on.
+
+use core::arch::asm;
+
+use crate::tls;
+
+extern "C" {
+    /// Defined by the linker at the start of the executable
+    static __executable_start: u8;
+}
+
+/// The address the executable was loaded at. Subtract it from return addresses
+/// to get addresses that `addr2line -e <executable>` understands.
+pub fn executable_base() -> usize {
+    unsafe { &__executable_start as *const u8 as usize }
+}
+
+/// The top of the current thread's stack, if it is known
+fn stack_base() -> Option<usize> {
+    let tls = tls::try_get_tls_ptr_fast()
+        .or_else(|| unsafe { tls::get_tls_ptr() }.ok())
+        .filter(|tls| !tls.is_null())?;
+
+    let stack_base = unsafe { (*tls).stack_base } as usize;
+
+    (stack_base != 0).then(|| stack_base)
+}
+
+/// Fill `frames` with the return addresses of the calling functions, innermost first.
+/// Returns the number of frames written, which is 0 if the thread has no TLS yet.
+#[inline(never)]
+pub fn return_addresses(frames: &mut [usize]) -> usize {
+    let stack_base = match stack_base() {
+        Some(stack_base) => stack_base,
+        None => return 0,
+    };
+
+    let mut rbp: usize;
+
+    // Safety: only reads a register
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+    let mut n = 0;
+
+    // Every frame is above the previous one, so this never leaves the stack
+    while n < frames.len() && rbp != 0 && rbp % 8 == 0 && rbp + 16 <= stack_base {
+        // Safety: `rbp` points into our stack, where the caller saved its `rbp`
+        // followed by our return address
+        let (next, return_address) = unsafe {
+            let frame = rbp as *const usize;
+
+            (*frame, *frame.add(1))
+        };
+
+        if return_address == 0 {
+            break;
+        }
+
+        frames[n] = return_address;
+        n += 1;
+
+        if next <= rbp {
+            break;
+        }
+
+        rbp = next;
+    }
+
+    n
+}
@@ -1,3 +1,7 @@
+/// Marks the end of the auxiliary vector
+const AT_NULL: usize = 0;
+pub const AT_HWCAP2: usize = 26;
+
 pub struct Environment {
     args_start: *const *const u8,
     env_start: *const *const u8,
@@ -61,6 +65,34 @@ impl Environment {
         }
     }
 
+    /// Look up `key` in the auxiliary vector the kernel placed after the environment
+    pub fn aux_value(&self, key: usize) -> Option<usize> {
+        unsafe {
+            let mut ptr = self.env_start;
+
+            while !(*ptr).is_null() {
+                ptr = ptr.add(1);
+            }
+
+            // skip the NULL terminating the environment
+            let mut aux_ptr = ptr.add(1) as *const [usize; 2];
+
+            loop {
+                let [aux_key, value] = *aux_ptr;
+
+                if aux_key == AT_NULL {
+                    return None;
+                }
+
+                if aux_key == key {
+                    return Some(value);
+                }
+
+                aux_ptr = aux_ptr.add(1);
+            }
+        }
+    }
+
     pub fn arg(&self, i: usize) -> Option<&'static str> {
         if i < self.n_args() {
             let string = unsafe { read_str(*self.args_start.add(i)) };
@@ -0,0 +1,298 @@
+//! A multi-producer, multi-consumer channel where every receiver sees every value.
+//!
+//! The channel keeps the last `capacity` values. A receiver that falls further
+//! behind skips the values it missed and is told how many there were.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+use crate::sync::Mutex;
+
+use super::SendError;
+
+/// Returned by `Receiver::recv`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// All senders were dropped and the receiver has seen all values
+    Closed,
+    /// The receiver fell behind and missed this many values.
+    /// The next `recv` returns the oldest value that is still in the channel.
+    Lagged(u64),
+}
+
+impl core::fmt::Display for RecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RecvError::Closed => write!(f, "channel is closed"),
+            RecvError::Lagged(n) => write!(f, "receiver lagged behind by {} values", n),
+        }
+    }
+}
+
+/// Returned by `Receiver::try_recv`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There is no new value yet
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+impl core::fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel is empty"),
+            TryRecvError::Closed => write!(f, "channel is closed"),
+            TryRecvError::Lagged(n) => write!(f, "receiver lagged behind by {} values", n),
+        }
+    }
+}
+
+struct Inner<T> {
+    /// The last `capacity` values
+    buffer: VecDeque<T>,
+    capacity: usize,
+    /// The sequence number of the first value in `buffer`
+    head: u64,
+    senders: usize,
+    receivers: usize,
+    /// Receivers waiting for a value, by their id
+    wakers: BTreeMap<u64, Waker>,
+    next_id: u64,
+}
+
+impl<T> Inner<T> {
+    /// The sequence number the next value will get
+    fn tail(&self) -> u64 {
+        self.head + self.buffer.len() as u64
+    }
+}
+
+type State<T> = Pin<Arc<Mutex<Inner<T>>>>;
+
+/// Create a channel that keeps the last `capacity` values for slow receivers
+pub fn channel<T: Clone + Send + Sync>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel capacity must be non-zero");
+
+    // Safety: the Mutex is `Pin`ed by the `Arc::pin`
+    let state = unsafe {
+        Arc::pin(Mutex::new(Inner {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            head: 0,
+            senders: 1,
+            receivers: 0,
+            wakers: BTreeMap::new(),
+            next_id: 0,
+        }))
+    };
+
+    let sender = Sender { state };
+    let receiver = sender.subscribe();
+
+    (sender, receiver)
+}
+
+/// The sending side of a broadcast channel
+pub struct Sender<T: Clone + Send + Sync> {
+    state: State<T>,
+}
+
+impl<T: Clone + Send + Sync> Sender<T> {
+    /// Send `value` to all current receivers, returning how many there are.
+    /// Fails if there are no receivers.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let (receivers, wakers, overwritten) = {
+            let mut inner = self.state.lock();
+
+            if inner.receivers == 0 {
+                return Err(SendError(value));
+            }
+
+            let overwritten = if inner.buffer.len() == inner.capacity {
+                inner.head += 1;
+                inner.buffer.pop_front()
+            } else {
+                None
+            };
+
+            inner.buffer.push_back(value);
+
+            let wakers = core::mem::take(&mut inner.wakers);
+
+            (inner.receivers, wakers, overwritten)
+        };
+
+        // drop the old value outside of the lock
+        drop(overwritten);
+
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+
+        Ok(receivers)
+    }
+
+    /// Create a new receiver, which sees all values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut inner = self.state.lock();
+
+        inner.receivers += 1;
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        Receiver {
+            state: self.state.clone(),
+            next: inner.tail(),
+            id,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.state.lock().receivers
+    }
+}
+
+impl<T: Clone + Send + Sync> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.state.lock().senders += 1;
+
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut inner = self.state.lock();
+
+            inner.senders -= 1;
+
+            if inner.senders > 0 {
+                return;
+            }
+
+            core::mem::take(&mut inner.wakers)
+        };
+
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+}
+
+/// The receiving side of a broadcast channel.
+/// Cloning it creates a receiver at the same position.
+pub struct Receiver<T: Clone + Send + Sync> {
+    state: State<T>,
+    /// The sequence number of the next value we will receive
+    next: u64,
+    /// Identifies our waker
+    id: u64,
+}
+
+impl<T: Clone + Send + Sync> Receiver<T> {
+    /// Wait for the next value
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    /// Take the next value without waiting
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let inner = self.state.lock();
+
+        if self.next < inner.head {
+            let missed = inner.head - self.next;
+            self.next = inner.head;
+
+            return Err(TryRecvError::Lagged(missed));
+        }
+
+        if self.next < inner.tail() {
+            let value = inner.buffer[(self.next - inner.head) as usize].clone();
+            self.next += 1;
+
+            return Ok(value);
+        }
+
+        if inner.senders == 0 {
+            Err(TryRecvError::Closed)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Poll::Ready(Ok(value)),
+                Err(TryRecvError::Lagged(missed)) => {
+                    return Poll::Ready(Err(RecvError::Lagged(missed)))
+                }
+                Err(TryRecvError::Closed) => return Poll::Ready(Err(RecvError::Closed)),
+                Err(TryRecvError::Empty) => {
+                    let mut inner = self.state.lock();
+
+                    // A value might have been sent since `try_recv`
+                    if self.next != inner.tail() || inner.senders == 0 {
+                        continue;
+                    }
+
+                    inner.wakers.insert(self.id, cx.waker().clone());
+
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let mut inner = self.state.lock();
+
+        inner.receivers += 1;
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        Self {
+            state: self.state.clone(),
+            next: self.next,
+            id,
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.state.lock();
+
+        inner.receivers -= 1;
+        inner.wakers.remove(&self.id);
+    }
+}
+
+/// Future returned by `Receiver::recv`
+pub struct Recv<'r, T: Clone + Send + Sync> {
+    receiver: &'r mut Receiver<T>,
+}
+
+impl<T: Clone + Send + Sync> Future for Recv<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_recv(cx)
+    }
+}
@@ -0,0 +1,88 @@
+//! Channels for passing values between tasks.
+//!
+//! Receiving (and sending on a full bounded channel) parks the task until the
+//! other side makes progress, instead of blocking the worker.
+
+use core::ops::{Deref, DerefMut};
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
+
+/// Returned by `send` if the receiving side was dropped. Holds the value that could not be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> core::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "channel is closed")
+    }
+}
+
+/// Returned by `try_send`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is at its capacity
+    Full(T),
+    /// The receiving side was dropped
+    Closed(T),
+}
+
+impl<T> core::fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "channel is full"),
+            TrySendError::Closed(_) => write!(f, "channel is closed"),
+        }
+    }
+}
+
+/// Returned by receiving if all senders were dropped and there are no values left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl core::fmt::Display for RecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "channel is closed")
+    }
+}
+
+/// Returned by `try_recv`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There is no value yet
+    Empty,
+    /// All senders were dropped and there are no values left
+    Closed,
+}
+
+impl core::fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel is empty"),
+            TryRecvError::Closed => write!(f, "channel is closed"),
+        }
+    }
+}
+
+/// Values in a channel, or on their way into one. They are only moved in and out
+/// while the channel's `Mutex` is locked or by the future that owns them, and never
+/// shared between tasks, so `T` does not need to be `Sync`.
+struct Unshared<T>(T);
+
+// Safety: `Unshared` is private, and the channels never hand out references into it
+unsafe impl<T: Send> Sync for Unshared<T> {}
+
+impl<T> Deref for Unshared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Unshared<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,386 @@
+//! Multi-producer, single-consumer channels, either bounded or unbounded
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+use crate::sync::Mutex;
+
+use super::{SendError, TryRecvError, TrySendError, Unshared};
+
+struct Inner<T> {
+    queue: Unshared<VecDeque<T>>,
+    /// `None` for unbounded channels
+    capacity: Option<usize>,
+    senders: usize,
+    /// The `Receiver` was closed or dropped
+    closed: bool,
+    recv_waker: Option<Waker>,
+    /// Senders waiting for space, by the order in which they started waiting
+    send_wakers: BTreeMap<u64, Waker>,
+    next_ticket: u64,
+}
+
+impl<T> Inner<T> {
+    fn is_full(&self) -> bool {
+        self.capacity
+            .map(|capacity| self.queue.len() >= capacity)
+            .unwrap_or(false)
+    }
+
+    /// Take the waker of the sender that has been waiting the longest
+    fn next_sender(&mut self) -> Option<Waker> {
+        let ticket = *self.send_wakers.keys().next()?;
+
+        self.send_wakers.remove(&ticket)
+    }
+}
+
+type State<T> = Pin<Arc<Mutex<Inner<T>>>>;
+
+fn state<T: Send>(capacity: Option<usize>) -> State<T> {
+    // Safety: the Mutex is `Pin`ed by the `Arc::pin`
+    unsafe {
+        Arc::pin(Mutex::new(Inner {
+            queue: Unshared(VecDeque::new()),
+            capacity,
+            senders: 1,
+            closed: false,
+            recv_waker: None,
+            send_wakers: BTreeMap::new(),
+            next_ticket: 0,
+        }))
+    }
+}
+
+/// Create a channel that holds up to `capacity` values.
+/// Sending waits while the channel is full.
+pub fn channel<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel capacity must be non-zero");
+
+    let state = state(Some(capacity));
+
+    (
+        Sender {
+            state: state.clone(),
+        },
+        Receiver { state },
+    )
+}
+
+/// Create a channel without a capacity limit. Sending never waits.
+pub fn unbounded<T: Send>() -> (UnboundedSender<T>, Receiver<T>) {
+    let state = state(None);
+
+    (
+        UnboundedSender {
+            state: state.clone(),
+        },
+        Receiver { state },
+    )
+}
+
+/// Push `value` if there is space, waking the receiver
+fn try_send<T: Send>(state: &State<T>, value: T) -> Result<(), TrySendError<T>> {
+    let waker = {
+        let mut inner = state.lock();
+
+        if inner.closed {
+            return Err(TrySendError::Closed(value));
+        }
+
+        if inner.is_full() {
+            return Err(TrySendError::Full(value));
+        }
+
+        inner.queue.push_back(value);
+
+        inner.recv_waker.take()
+    };
+
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+
+    Ok(())
+}
+
+fn clone_sender<T: Send>(state: &State<T>) -> State<T> {
+    state.lock().senders += 1;
+
+    state.clone()
+}
+
+fn drop_sender<T: Send>(state: &State<T>) {
+    let waker = {
+        let mut inner = state.lock();
+
+        inner.senders -= 1;
+
+        if inner.senders > 0 {
+            return;
+        }
+
+        inner.recv_waker.take()
+    };
+
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// The sending side of a bounded channel
+pub struct Sender<T: Send> {
+    state: State<T>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Send `value`, waiting for space if the channel is full.
+    /// Fails if the `Receiver` was closed or dropped.
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            sender: self,
+            value: Unshared(Some(value)),
+            ticket: None,
+        }
+    }
+
+    /// Send `value` without waiting
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        try_send(&self.state, value)
+    }
+
+    /// Returns true if the `Receiver` was closed or dropped
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: clone_sender(&self.state),
+        }
+    }
+}
+
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.state);
+    }
+}
+
+/// Future returned by `Sender::send`
+pub struct SendFuture<'s, T: Send> {
+    sender: &'s Sender<T>,
+    value: Unshared<Option<T>>,
+    /// Our place in the queue of waiting senders
+    ticket: Option<u64>,
+}
+
+// Safety: `value` is never pinned
+impl<T: Send> Unpin for SendFuture<'_, T> {}
+
+impl<T: Send> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut value = self
+            .value
+            .take()
+            .expect("SendFuture polled after completion");
+
+        loop {
+            match try_send(&self.sender.state, value) {
+                Ok(()) => {
+                    if let Some(ticket) = self.ticket.take() {
+                        self.sender.state.lock().send_wakers.remove(&ticket);
+                    }
+
+                    return Poll::Ready(Ok(()));
+                }
+                Err(TrySendError::Closed(value)) => return Poll::Ready(Err(SendError(value))),
+                Err(TrySendError::Full(full)) => {
+                    value = full;
+
+                    let mut inner = self.sender.state.lock();
+
+                    // The receiver might have made space since `try_send`
+                    if !inner.is_full() || inner.closed {
+                        continue;
+                    }
+
+                    let ticket = self.ticket.unwrap_or_else(|| {
+                        let ticket = inner.next_ticket;
+                        inner.next_ticket += 1;
+
+                        ticket
+                    });
+
+                    inner.send_wakers.insert(ticket, cx.waker().clone());
+                    drop(inner);
+
+                    self.ticket = Some(ticket);
+                    *self.value = Some(value);
+
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Send> Drop for SendFuture<'_, T> {
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            let waker = {
+                let mut inner = self.sender.state.lock();
+
+                // If we were woken, pass the free slot on to the next sender
+                if inner.send_wakers.remove(&ticket).is_none() && !inner.is_full() {
+                    inner.next_sender()
+                } else {
+                    None
+                }
+            };
+
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// The sending side of an unbounded channel
+pub struct UnboundedSender<T: Send> {
+    state: State<T>,
+}
+
+impl<T: Send> UnboundedSender<T> {
+    /// Send `value`. Fails if the `Receiver` was closed or dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        try_send(&self.state, value).map_err(|err| match err {
+            TrySendError::Closed(value) | TrySendError::Full(value) => SendError(value),
+        })
+    }
+
+    /// Returns true if the `Receiver` was closed or dropped
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+}
+
+impl<T: Send> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: clone_sender(&self.state),
+        }
+    }
+}
+
+impl<T: Send> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.state);
+    }
+}
+
+/// The receiving side of a bounded or unbounded channel
+pub struct Receiver<T: Send> {
+    state: State<T>,
+}
+
+impl<T: Send> Receiver<T> {
+    /// Wait for the next value.
+    /// Returns `None` once all senders were dropped and the channel is empty.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    /// Take the next value without waiting
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let (value, waker) = {
+            let mut inner = self.state.lock();
+
+            match inner.queue.pop_front() {
+                Some(value) => (value, inner.next_sender()),
+                None if inner.senders == 0 => return Err(TryRecvError::Closed),
+                None => return Err(TryRecvError::Empty),
+            }
+        };
+
+        // There is space for one more value now
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+
+        Ok(value)
+    }
+
+    /// Prevent further sends. Values that were already sent can still be received.
+    pub fn close(&mut self) {
+        let wakers = {
+            let mut inner = self.state.lock();
+
+            inner.closed = true;
+
+            core::mem::take(&mut inner.send_wakers)
+        };
+
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Poll::Ready(Some(value)),
+                Err(TryRecvError::Closed) => return Poll::Ready(None),
+                Err(TryRecvError::Empty) => {
+                    let mut inner = self.state.lock();
+
+                    // A value might have been sent since `try_recv`
+                    if !inner.queue.is_empty() || inner.senders == 0 {
+                        continue;
+                    }
+
+                    inner.recv_waker = Some(cx.waker().clone());
+
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+
+        // drop the values outside of the lock
+        let queue = core::mem::take(&mut *self.state.lock().queue);
+        drop(queue);
+
+        self.state.lock().recv_waker = None;
+    }
+}
+
+/// Future returned by `Receiver::recv`
+pub struct Recv<'r, T: Send> {
+    receiver: &'r mut Receiver<T>,
+}
+
+impl<T: Send> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_recv(cx)
+    }
+}
@@ -0,0 +1,167 @@
+//! A channel for sending a single value
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::sync::Arc;
+
+use crate::sync::Mutex;
+
+use super::{RecvError, TryRecvError, Unshared};
+
+struct Inner<T> {
+    value: Unshared<Option<T>>,
+    waker: Option<Waker>,
+    /// The `Sender` was used or dropped
+    sent: bool,
+    /// The `Receiver` was closed or dropped
+    closed: bool,
+    /// The `Receiver` returned `Ready`
+    finished: bool,
+}
+
+type State<T> = Pin<Arc<Mutex<Inner<T>>>>;
+
+/// Sends a value to the `Receiver`. Dropping it without sending closes the channel.
+pub struct Sender<T: Send> {
+    state: State<T>,
+}
+
+/// Awaiting it returns the value, or `RecvError` if the `Sender` was dropped.
+pub struct Receiver<T: Send> {
+    state: State<T>,
+}
+
+/// Create a channel for sending a single value
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    // Safety: the Mutex is `Pin`ed by the `Arc::pin`
+    let state = unsafe {
+        Arc::pin(Mutex::new(Inner {
+            value: Unshared(None),
+            waker: None,
+            sent: false,
+            closed: false,
+            finished: false,
+        }))
+    };
+
+    (
+        Sender {
+            state: state.clone(),
+        },
+        Receiver { state },
+    )
+}
+
+impl<T: Send> Sender<T> {
+    /// Send `value`. Returns it if the `Receiver` was closed or dropped.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let waker = {
+            let mut inner = self.state.lock();
+
+            if inner.closed {
+                return Err(value);
+            }
+
+            *inner.value = Some(value);
+            inner.sent = true;
+
+            inner.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the `Receiver` was closed or dropped
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+}
+
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut inner = self.state.lock();
+
+            if inner.sent {
+                return;
+            }
+
+            inner.sent = true;
+
+            inner.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: Send> Receiver<T> {
+    /// Take the value without waiting
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = self.state.lock();
+
+        if let Some(value) = inner.value.take() {
+            Ok(value)
+        } else if inner.sent {
+            Err(TryRecvError::Closed)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Prevent the `Sender` from sending. A value that was already sent can still be taken.
+    pub fn close(&mut self) {
+        self.state.lock().closed = true;
+    }
+}
+
+impl<T: Send> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.state.lock();
+
+        assert!(
+            !inner.finished,
+            "oneshot::Receiver polled after it returned `Ready`"
+        );
+
+        if let Some(value) = inner.value.take() {
+            inner.finished = true;
+
+            Poll::Ready(Ok(value))
+        } else if inner.sent || inner.closed {
+            inner.finished = true;
+
+            Poll::Ready(Err(RecvError))
+        } else {
+            inner.waker = Some(cx.waker().clone());
+
+            Poll::Pending
+        }
+    }
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.state.lock();
+
+        inner.closed = true;
+        inner.waker = None;
+
+        // drop the value outside of the lock
+        let value = inner.value.take();
+        drop(inner);
+        drop(value);
+    }
+}
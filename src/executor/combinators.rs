@@ -0,0 +1,444 @@
+//! Ways to wait on several futures at once.
+//!
+//! `join!` and `join_all` wait for all futures, `select!` for the first one,
+//! and `FuturesUnordered` yields outputs in the order the futures finish.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, task::Wake, vec::Vec};
+
+use crate::sync::Mutex;
+
+/// A future that calls `f` whenever it is polled
+pub struct PollFn<F> {
+    f: F,
+}
+
+impl<F> Unpin for PollFn<F> {}
+
+/// Create a future from a closure that is called whenever the future is polled
+pub fn poll_fn<T, F: FnMut(&mut Context<'_>) -> Poll<T>>(f: F) -> PollFn<F> {
+    PollFn { f }
+}
+
+impl<T, F: FnMut(&mut Context<'_>) -> Poll<T>> Future for PollFn<F> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        (self.f)(cx)
+    }
+}
+
+/// A future which keeps its output once it finished, so it can be polled
+/// together with other futures.
+pub enum MaybeDone<F: Future> {
+    Future(F),
+    Done(F::Output),
+    /// The output was taken, or the future was dropped
+    Gone,
+}
+
+impl<F: Future> MaybeDone<F> {
+    pub fn new(fut: F) -> Self {
+        Self::Future(fut)
+    }
+
+    /// Take the output, if the future finished
+    pub fn take_output(self: Pin<&mut Self>) -> Option<F::Output> {
+        match &*self {
+            Self::Done(_) => {}
+            _ => return None,
+        }
+
+        // Safety: the output is not pinned, we only move it out of the `Done` variant
+        match core::mem::replace(unsafe { self.get_unchecked_mut() }, Self::Gone) {
+            Self::Done(output) => Some(output),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Drop the future if it did not finish yet
+    pub fn cancel(mut self: Pin<&mut Self>) {
+        if let Self::Future(_) = &*self {
+            // drops the future in place
+            self.set(Self::Gone);
+        }
+    }
+}
+
+impl<F: Future> Future for MaybeDone<F> {
+    /// Ready once the output is available through `take_output`
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the future is never moved out of `self`
+        let output = match unsafe { self.as_mut().get_unchecked_mut() } {
+            Self::Future(fut) => match unsafe { Pin::new_unchecked(fut) }.poll(cx) {
+                Poll::Ready(output) => output,
+                Poll::Pending => return Poll::Pending,
+            },
+            Self::Done(_) => return Poll::Ready(()),
+            Self::Gone => panic!("MaybeDone polled after its output was taken"),
+        };
+
+        self.set(Self::Done(output));
+
+        Poll::Ready(())
+    }
+}
+
+/// Poll futures concurrently, until all of them finished. Evaluates to a
+/// tuple of their outputs. Can only be used in async code.
+///
+/// `join!(a, b, c).await` is wrong, `join!` already awaits the futures:
+/// `let (a, b, c) = join!(a, b, c);`
+pub macro join {
+    // Give each future a list of `_`s, one for each future before it, to get
+    // at its slot in the tuple with `let (_, _, fut, ..) = futures`.
+    (@{ ( $($count:tt)* ) $( ( $($skip:tt)* ) $fut:expr, )* } $next:expr $(, $rest:expr)* $(,)?) => {
+        $crate::executor::join!(@{
+            ( $($count)* _ )
+            $( ( $($skip)* ) $fut, )*
+            ( $($count)* ) $next,
+        } $($rest),*)
+    },
+    (@{ ( $($count:tt)* ) $( ( $($skip:tt)* ) $fut:expr, )* }) => {{
+        let mut futures = ( $( $crate::executor::MaybeDone::new($fut), )* );
+
+        // Safety: `futures` is shadowed, so it can't be moved anymore
+        let mut futures = unsafe { ::core::pin::Pin::new_unchecked(&mut futures) };
+
+        $crate::executor::poll_fn(|cx| {
+            let mut pending = false;
+
+            $(
+                // Safety: the fields of the pinned tuple are never moved
+                let ( $($skip,)* fut, .. ) = unsafe { futures.as_mut().get_unchecked_mut() };
+                let fut = unsafe { ::core::pin::Pin::new_unchecked(fut) };
+
+                if ::core::future::Future::poll(fut, cx).is_pending() {
+                    pending = true;
+                }
+            )*
+
+            if pending {
+                ::core::task::Poll::Pending
+            } else {
+                ::core::task::Poll::Ready(())
+            }
+        }).await;
+
+        ( $({
+            let ( $($skip,)* fut, .. ) = unsafe { futures.as_mut().get_unchecked_mut() };
+            let fut = unsafe { ::core::pin::Pin::new_unchecked(fut) };
+
+            fut.take_output().unwrap()
+        },)* )
+    }},
+    ($($fut:expr),+ $(,)?) => {
+        $crate::executor::join!(@{ () } $($fut),+)
+    },
+}
+
+/// Wait for the first of several futures and run the branch of the future
+/// that finished with its output. The other futures are dropped before the
+/// branch runs. Can only be used in async code.
+///
+/// ```ignore
+/// select! {
+///     value = rx.recv() => handle(value),
+///     _ = sleep(Duration::from_secs(1)) => return,
+/// }
+/// ```
+///
+/// The patterns must be irrefutable. If several futures are ready at once,
+/// the first branch wins.
+pub macro select {
+    (@{ ( $($count:tt)* ) $( ( $($skip:tt)* ) $pat:pat = $fut:expr => $body:expr, )* }
+        $next_pat:pat = $next_fut:expr => $next_body:expr $(, $($rest:tt)*)?) => {
+        $crate::executor::select!(@{
+            ( $($count)* _ )
+            $( ( $($skip)* ) $pat = $fut => $body, )*
+            ( $($count)* ) $next_pat = $next_fut => $next_body,
+        } $($($rest)*)?)
+    },
+    (@{ ( $($count:tt)* ) $( ( $($skip:tt)* ) $pat:pat = $fut:expr => $body:expr, )* }) => {{
+        let mut futures = ( $( $crate::executor::MaybeDone::new($fut), )* );
+
+        // Safety: `futures` is shadowed, so it can't be moved anymore
+        let mut futures = unsafe { ::core::pin::Pin::new_unchecked(&mut futures) };
+
+        $crate::executor::poll_fn(|cx| {
+            $(
+                // Safety: the fields of the pinned tuple are never moved
+                let ( $($skip,)* fut, .. ) = unsafe { futures.as_mut().get_unchecked_mut() };
+                let fut = unsafe { ::core::pin::Pin::new_unchecked(fut) };
+
+                if ::core::future::Future::poll(fut, cx).is_ready() {
+                    return ::core::task::Poll::Ready(());
+                }
+            )*
+
+            ::core::task::Poll::Pending
+        }).await;
+
+        // cancel the other branches
+        $(
+            let ( $($skip,)* fut, .. ) = unsafe { futures.as_mut().get_unchecked_mut() };
+            unsafe { ::core::pin::Pin::new_unchecked(fut) }.cancel();
+        )*
+
+        $(
+            if let ::core::option::Option::Some(output) = {
+                let ( $($skip,)* fut, .. ) = unsafe { futures.as_mut().get_unchecked_mut() };
+                unsafe { ::core::pin::Pin::new_unchecked(fut) }.take_output()
+            } {
+                let $pat = output;
+                $body
+            } else
+        )*
+        {
+            unreachable!("no select! branch finished")
+        }
+    }},
+    ($($branches:tt)+) => {
+        $crate::executor::select!(@{ () } $($branches)+)
+    },
+}
+
+/// Future returned by `join_all`
+pub struct JoinAll<F: Future> {
+    futures: Pin<Box<[MaybeDone<F>]>>,
+}
+
+/// Wait for all `futures`, returning their outputs in the same order
+pub fn join_all<I>(futures: I) -> JoinAll<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    let futures: Box<[_]> = futures.into_iter().map(MaybeDone::new).collect();
+
+    JoinAll {
+        futures: futures.into(),
+    }
+}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the elements of the pinned slice are never moved
+        let futures = unsafe { self.futures.as_mut().get_unchecked_mut() };
+
+        let mut pending = false;
+
+        for fut in futures.iter_mut() {
+            if unsafe { Pin::new_unchecked(fut) }.poll(cx).is_pending() {
+                pending = true;
+            }
+        }
+
+        if pending {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(
+            futures
+                .iter_mut()
+                .map(|fut| unsafe { Pin::new_unchecked(fut) }.take_output().unwrap())
+                .collect(),
+        )
+    }
+}
+
+/// Shared between a `FuturesUnordered` and the wakers of its futures
+struct ReadyQueue {
+    /// Slots of the futures that were woken
+    ready: Mutex<VecDeque<usize>>,
+    /// The waker of the task polling the `FuturesUnordered`
+    waker: Mutex<Option<Waker>>,
+}
+
+struct SlotWaker {
+    slot: usize,
+    /// Whether `slot` is in the ready queue
+    queued: AtomicBool,
+    queue: Pin<Arc<ReadyQueue>>,
+}
+
+impl Wake for SlotWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.queue.ready.lock().push_back(self.slot);
+
+        if let Some(waker) = self.queue.waker.lock().take() {
+            waker.wake();
+        }
+    }
+}
+
+struct Slot<F> {
+    fut: Option<Pin<Box<F>>>,
+    waker: Arc<SlotWaker>,
+}
+
+/// A set of futures which are polled only when they are woken.
+/// `next` returns their outputs in the order in which they finish.
+pub struct FuturesUnordered<F: Future> {
+    slots: Vec<Slot<F>>,
+    /// Slots without a future
+    free: Vec<usize>,
+    queue: Pin<Arc<ReadyQueue>>,
+}
+
+impl<F: Future> FuturesUnordered<F> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            // Safety: the Mutexes are `Pin`ed by the `Arc::pin`
+            queue: unsafe {
+                Arc::pin(ReadyQueue {
+                    ready: Mutex::new(VecDeque::new()),
+                    waker: Mutex::new(None),
+                })
+            },
+        }
+    }
+
+    /// The number of futures that did not finish yet
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add a future to the set. It is first polled by the next call to `next`.
+    pub fn push(&mut self, fut: F) {
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(Slot {
+                    fut: None,
+                    waker: Arc::new(SlotWaker {
+                        slot: self.slots.len(),
+                        queued: AtomicBool::new(false),
+                        queue: self.queue.clone(),
+                    }),
+                });
+
+                self.slots.len() - 1
+            }
+        };
+
+        self.slots[slot].fut = Some(Box::pin(fut));
+
+        self.slots[slot].waker.wake_by_ref();
+    }
+
+    /// Poll the futures that were woken, until one of them finishes.
+    /// Returns `Ready(None)` if the set is empty.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<F::Output>> {
+        *self.queue.waker.lock() = Some(cx.waker().clone());
+
+        // Don't starve other tasks if our futures keep waking themselves
+        let mut budget = self.slots.len();
+
+        loop {
+            if self.is_empty() {
+                return Poll::Ready(None);
+            }
+
+            let slot = match self.queue.ready.lock().pop_front() {
+                Some(slot) => slot,
+                None => return Poll::Pending,
+            };
+
+            if budget == 0 {
+                self.slots[slot]
+                    .waker
+                    .queued
+                    .store(false, Ordering::Release);
+                self.slots[slot].waker.wake_by_ref();
+
+                return Poll::Pending;
+            }
+            budget -= 1;
+
+            let Slot { fut, waker } = &mut self.slots[slot];
+
+            // clear the flag before polling, so wake ups during the poll are not lost
+            waker.queued.store(false, Ordering::Release);
+
+            // The future might have finished after it was woken
+            let fut_ref = match fut.as_mut() {
+                Some(fut) => fut,
+                None => continue,
+            };
+
+            let child_waker = Waker::from(waker.clone());
+
+            if let Poll::Ready(output) = fut_ref
+                .as_mut()
+                .poll(&mut Context::from_waker(&child_waker))
+            {
+                *fut = None;
+                self.free.push(slot);
+
+                return Poll::Ready(Some(output));
+            }
+        }
+    }
+
+    /// Wait for the next future to finish. Returns `None` if the set is empty.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_, F> {
+        Next { set: self }
+    }
+}
+
+impl<F: Future> Default for FuturesUnordered<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Future> FromIterator<F> for FuturesUnordered<F> {
+    fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Self {
+        let mut set = Self::new();
+
+        for fut in iter {
+            set.push(fut);
+        }
+
+        set
+    }
+}
+
+/// Future returned by `FuturesUnordered::next`
+pub struct Next<'s, F: Future> {
+    set: &'s mut FuturesUnordered<F>,
+}
+
+impl<F: Future> Future for Next<'_, F> {
+    type Output = Option<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.set.poll_next(cx)
+    }
+}
@@ -0,0 +1,251 @@
+//! A fixed capacity Chase–Lev work-stealing deque.
+//!
+//! The owning `Worker` pushes and pops at the bottom, any number of `Stealer`s
+//! take items from the top. Only the last item is contended, which is resolved
+//! with a CAS on `top`. The memory orderings follow "Correct and Efficient
+//! Work-Stealing for Weak Memory Models" (Lê et al., 2013).
+//!
+//! Items are `Arc`s, which are stored as raw pointers, so that a stealer which
+//! lost a race only ever reads a stale pointer, and never a torn value.
+
+use core::{
+    cell::Cell,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{fence, AtomicIsize, AtomicPtr, Ordering},
+};
+
+use alloc::sync::Arc;
+
+/// Maximum number of items in a deque
+pub const CAPACITY: usize = 256;
+
+struct Inner<T> {
+    /// Index of the oldest item. Only ever incremented.
+    top: AtomicIsize,
+    /// Index one past the newest item. Only written by the `Worker`.
+    bottom: AtomicIsize,
+    buffer: [AtomicPtr<T>; CAPACITY],
+    /// The deque owns the `Arc`s in `buffer`
+    _marker: PhantomData<Arc<T>>,
+}
+
+impl<T> Inner<T> {
+    fn slot(&self, i: isize) -> &AtomicPtr<T> {
+        &self.buffer[i as usize % CAPACITY]
+    }
+
+    fn len(&self) -> usize {
+        let top = self.top.load(Ordering::Acquire);
+        fence(Ordering::SeqCst);
+        let bottom = self.bottom.load(Ordering::Acquire);
+
+        (bottom - top).max(0) as usize
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let top = *self.top.get_mut();
+        let bottom = *self.bottom.get_mut();
+
+        for i in top..bottom {
+            // Safety: the items between `top` and `bottom` are owned by the deque
+            drop(unsafe { Arc::from_raw(self.slot(i).load(Ordering::Relaxed)) });
+        }
+    }
+}
+
+/// The owning end of a deque. There is only one per deque.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    /// Only one thread may push and pop
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+/// A handle which takes items from the top of a `Worker`'s deque
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// The result of `Stealer::steal`
+pub enum Steal<T> {
+    Empty,
+    /// Lost a race against another thread, the deque might not be empty
+    Retry,
+    Success(Arc<T>),
+}
+
+/// Create a new empty deque
+pub fn new<T>() -> (Worker<T>, Stealer<T>) {
+    let inner = Arc::new(Inner {
+        top: AtomicIsize::new(0),
+        bottom: AtomicIsize::new(0),
+        buffer: [(); CAPACITY].map(|_| AtomicPtr::new(ptr::null_mut())),
+        _marker: PhantomData,
+    });
+
+    let stealer = Stealer {
+        inner: inner.clone(),
+    };
+
+    let worker = Worker {
+        inner,
+        _not_sync: PhantomData,
+    };
+
+    (worker, stealer)
+}
+
+impl<T> Worker<T> {
+    /// Push an item to the bottom of the deque.
+    /// Returns the item if the deque is full.
+    pub fn push(&self, item: Arc<T>) -> Result<(), Arc<T>> {
+        let inner = &*self.inner;
+
+        let bottom = inner.bottom.load(Ordering::Relaxed);
+        let top = inner.top.load(Ordering::Acquire);
+
+        if bottom - top >= CAPACITY as isize {
+            return Err(item);
+        }
+
+        inner
+            .slot(bottom)
+            .store(Arc::into_raw(item) as *mut T, Ordering::Relaxed);
+
+        // publish the item before the new `bottom`
+        fence(Ordering::Release);
+        inner.bottom.store(bottom + 1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Pop the newest item from the bottom of the deque
+    pub fn pop(&self) -> Option<Arc<T>> {
+        let inner = &*self.inner;
+
+        let bottom = inner.bottom.load(Ordering::Relaxed) - 1;
+        inner.bottom.store(bottom, Ordering::Relaxed);
+
+        // stealers must see the reserved `bottom` before we read `top`
+        fence(Ordering::SeqCst);
+
+        let top = inner.top.load(Ordering::Relaxed);
+
+        if top > bottom {
+            // the deque was empty
+            inner.bottom.store(bottom + 1, Ordering::Relaxed);
+
+            return None;
+        }
+
+        let item = inner.slot(bottom).load(Ordering::Relaxed);
+
+        if top == bottom {
+            // This is the last item, stealers might be trying to take it as well
+            let won = inner
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+
+            inner.bottom.store(bottom + 1, Ordering::Relaxed);
+
+            if !won {
+                return None;
+            }
+        }
+
+        // Safety: we removed the item from the deque, so we own its reference
+        Some(unsafe { Arc::from_raw(item) })
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Stealer<T> {
+    /// Take the oldest item from the top of the deque
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+
+        let top = inner.top.load(Ordering::Acquire);
+        fence(Ordering::SeqCst);
+        let bottom = inner.bottom.load(Ordering::Acquire);
+
+        if top >= bottom {
+            return Steal::Empty;
+        }
+
+        // If we lose the race below, this might already have been
+        // overwritten. We only use it if the CAS succeeds.
+        let item = inner.slot(top).load(Ordering::Relaxed);
+
+        if inner
+            .top
+            .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            return Steal::Retry;
+        }
+
+        // Safety: we removed the item from the deque, so we own its reference
+        Steal::Success(unsafe { Arc::from_raw(item) })
+    }
+
+    /// Take about half of the items, oldest first. Returns the oldest one,
+    /// and pushes the others to `dest`.
+    ///
+    /// The items are taken one at a time, since the `Worker` pops from the
+    /// bottom without a CAS while more than one item is left, so a single CAS
+    /// can't claim several items at once.
+    pub fn steal_batch(&self, dest: &Worker<T>) -> Steal<T> {
+        let first = match self.steal() {
+            Steal::Success(item) => item,
+            other => return other,
+        };
+
+        let n = (self.len() + 1) / 2;
+        let n = n.min(CAPACITY - dest.len());
+
+        for _ in 0..n {
+            match self.steal() {
+                Steal::Success(item) => {
+                    if dest.push(item).is_err() {
+                        unreachable!("deque overflowed while it was refilled");
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        Steal::Success(first)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
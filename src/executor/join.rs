@@ -0,0 +1,201 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::sync::Arc;
+
+use crate::sync::Mutex;
+
+use super::Task;
+
+/// The reason a task did not produce a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted through `JoinHandle::abort`
+    Aborted,
+    /// The task was dropped before it finished, because its `Executor` was dropped
+    Shutdown,
+}
+
+impl core::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            JoinError::Aborted => write!(f, "task was aborted"),
+            JoinError::Shutdown => write!(f, "task was dropped at executor shutdown"),
+        }
+    }
+}
+
+pub(super) struct JoinInner<T> {
+    result: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+    finished: bool,
+    aborted: bool,
+    detached: bool,
+}
+
+pub(super) type JoinState<T> = Pin<Arc<Mutex<JoinInner<T>>>>;
+
+pub(super) fn join_state<T: Send + Sync>() -> JoinState<T> {
+    // Safety: the Mutex is `Pin`ed by the `Arc::pin`
+    unsafe {
+        Arc::pin(Mutex::new(JoinInner {
+            result: None,
+            waker: None,
+            finished: false,
+            aborted: false,
+            detached: false,
+        }))
+    }
+}
+
+fn complete<T: Send + Sync>(state: &JoinState<T>, result: Result<T, JoinError>) {
+    let waker = {
+        let mut inner = state.lock();
+
+        inner.finished = true;
+
+        // Nobody is interested in the result, drop it right away
+        if !inner.detached {
+            inner.result = Some(result);
+        }
+
+        inner.waker.take()
+    };
+
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Wraps a spawned future and reports its output to the task's `JoinHandle`.
+/// If it is dropped before the future finished, the `JoinHandle` is told why.
+pub(super) struct Joined<F: Future>
+where
+    F::Output: Send + Sync,
+{
+    fut: F,
+    state: JoinState<F::Output>,
+    finished: bool,
+}
+
+impl<F: Future> Joined<F>
+where
+    F::Output: Send + Sync,
+{
+    pub(super) fn new(fut: F, state: JoinState<F::Output>) -> Self {
+        Self {
+            fut,
+            state,
+            finished: false,
+        }
+    }
+}
+
+impl<F: Future> Future for Joined<F>
+where
+    F::Output: Send + Sync,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `fut` is never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+
+        match fut.poll(cx) {
+            Poll::Ready(res) => {
+                this.finished = true;
+                complete(&this.state, Ok(res));
+
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<F: Future> Drop for Joined<F>
+where
+    F::Output: Send + Sync,
+{
+    fn drop(&mut self) {
+        if !self.finished {
+            let reason = if self.state.lock().aborted {
+                JoinError::Aborted
+            } else {
+                JoinError::Shutdown
+            };
+
+            complete(&self.state, Err(reason));
+        }
+    }
+}
+
+/// A handle to a task spawned on an `Executor`.
+///
+/// Awaiting it returns the task's output, or the reason it did not finish.
+/// Dropping the handle detaches the task.
+pub struct JoinHandle<T: Send + Sync> {
+    state: JoinState<T>,
+    task: Arc<Task>,
+}
+
+impl<T: Send + Sync> JoinHandle<T> {
+    pub(super) fn new(state: JoinState<T>, task: Arc<Task>) -> Self {
+        Self { state, task }
+    }
+
+    /// Returns true if the task finished or was dropped
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().finished
+    }
+
+    /// Cancel the task. It is dropped the next time a worker would poll it.
+    /// Awaiting the handle returns `JoinError::Aborted`, unless the task
+    /// already finished.
+    pub fn abort(&self) {
+        self.state.lock().aborted = true;
+
+        self.task.clone().abort();
+    }
+
+    /// Let the task run to completion in the background, discarding its output
+    pub fn detach(self) {
+        drop(self)
+    }
+}
+
+impl<T: Send + Sync> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.state.lock();
+
+        if let Some(result) = inner.result.take() {
+            Poll::Ready(result)
+        } else if inner.finished {
+            panic!("JoinHandle polled after it returned `Ready`");
+        } else {
+            inner.waker = Some(cx.waker().clone());
+
+            Poll::Pending
+        }
+    }
+}
+
+impl<T: Send + Sync> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        let mut inner = self.state.lock();
+
+        inner.detached = true;
+        inner.waker = None;
+
+        // drop the result outside of the lock
+        let result = inner.result.take();
+        drop(inner);
+        drop(result);
+    }
+}
@@ -0,0 +1,558 @@
+use core::{
+    cell::{Cell, RefCell},
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    rc::{Rc, Weak},
+    sync::Arc,
+    task::Wake,
+};
+
+use crate::{
+    io::Fd,
+    sync::Mutex,
+    syscalls::{
+        self, epoll_create1, epoll_ctl, epoll_wait, eventfd2, helper::SyscallErrorKind,
+        EpollCreateFlags, EpollEvent, EpollFlags, EpollOp, EventFdFlags, SyscallResult,
+    },
+};
+
+use super::{reactor::REACTOR, uring::URING, JoinError, REACTOR_POLL_INTERVAL};
+
+/// The id `block_on` uses for the future it drives
+const MAIN_TASK: u64 = u64::MAX;
+
+const UNPARK_TOKEN: u64 = 0;
+const REACTOR_TOKEN: u64 = 1;
+
+/// Blocks the thread running a `LocalExecutor` until one of its tasks is
+/// woken, or the reactor has new events.
+struct Parker {
+    epoll: Fd,
+    event_fd: Fd,
+    sleeping: AtomicBool,
+}
+
+impl Parker {
+    fn new() -> SyscallResult<Self> {
+        let reactor = REACTOR.epoll()?;
+
+        unsafe {
+            let epoll = epoll_create1(EpollCreateFlags::CLOEXEC)?;
+
+            let event_fd =
+                eventfd2(0, EventFdFlags::CLOEXEC | EventFdFlags::NONBLOCK).map_err(|err| {
+                    syscalls::close(Fd(epoll)).expect("Failed to close epoll");
+                    err
+                })?;
+
+            // From here on, dropping the parker closes both fds if we fail
+            let parker = Self {
+                epoll: Fd(epoll),
+                event_fd: Fd(event_fd),
+                sleeping: AtomicBool::new(false),
+            };
+
+            let mut event = EpollEvent {
+                events: EpollFlags::IN,
+                data: UNPARK_TOKEN,
+            };
+            epoll_ctl(epoll, EpollOp::Add, event_fd, &mut event as *mut _)?;
+
+            // Edge triggered, so that events which are left for the
+            // executor's workers don't keep waking us up
+            let mut event = EpollEvent {
+                events: EpollFlags::IN | EpollFlags::ET,
+                data: REACTOR_TOKEN,
+            };
+            epoll_ctl(epoll, EpollOp::Add, reactor, &mut event as *mut _)?;
+
+            Ok(parker)
+        }
+    }
+
+    fn park(&self) {
+        let mut events = [EpollEvent::empty(); 2];
+
+        let res = unsafe { epoll_wait(self.epoll.0, events.as_mut_ptr(), 2, -1) };
+
+        match res {
+            Ok(_) => {}
+            Err(err) if err.kind() == SyscallErrorKind::EINTR => {}
+            Err(err) => panic!("Failed to park local executor: {}", err),
+        }
+
+        // reset the event counter
+        let mut buf = [0; 8];
+        match syscalls::read(self.event_fd.0, &mut buf) {
+            // We were woken by the reactor
+            Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {}
+            res => {
+                res.expect("Failed to read from eventfd");
+            }
+        }
+    }
+
+    fn unpark(&self) {
+        if !self.sleeping.load(Ordering::SeqCst) {
+            return;
+        }
+
+        match syscalls::write(self.event_fd.0, &1u64.to_ne_bytes()) {
+            // The counter is about to overflow, so there is already a pending wake up
+            Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {}
+            res => {
+                res.expect("Failed to unpark local executor");
+            }
+        }
+    }
+}
+
+impl Drop for Parker {
+    fn drop(&mut self) {
+        syscalls::close(self.event_fd).expect("Failed to close eventfd");
+        syscalls::close(self.epoll).expect("Failed to close epoll");
+    }
+}
+
+/// The part of a `LocalExecutor` that wakers on other threads use
+struct Inbox {
+    /// Ids of the tasks that need to be polled
+    woken: Mutex<VecDeque<u64>>,
+    parker: Parker,
+}
+
+struct TaskWaker {
+    id: u64,
+    /// Set while the task's id is in `woken`, so that it is only queued once
+    scheduled: AtomicBool,
+    inbox: Pin<Arc<Inbox>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.inbox.woken.lock().push_back(self.id);
+
+        self.inbox.parker.unpark();
+    }
+}
+
+struct LocalTask {
+    fut: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+    /// Drop the task instead of polling it
+    aborted: Rc<Cell<bool>>,
+}
+
+struct LocalInner {
+    inbox: Pin<Arc<Inbox>>,
+    tasks: RefCell<BTreeMap<u64, LocalTask>>,
+    next_id: Cell<u64>,
+    /// Set while `block_on` is running
+    running: Cell<bool>,
+}
+
+/// Runs futures which are not `Send` on the thread that created it.
+///
+/// Nothing runs in the background: tasks only make progress while
+/// `LocalExecutor::block_on` is running.
+pub struct LocalExecutor {
+    inner: Rc<LocalInner>,
+}
+
+/// Spawns tasks onto a `LocalExecutor` from inside its own tasks
+#[derive(Clone)]
+pub struct LocalSpawner {
+    inner: Weak<LocalInner>,
+}
+
+impl LocalExecutor {
+    pub fn new() -> Self {
+        let parker = Parker::new().expect("Failed to set up local executor");
+
+        // Safety: the Arc `Pin`s the Mutex
+        let inbox = unsafe {
+            Arc::pin(Inbox {
+                woken: Mutex::new(VecDeque::new()),
+                parker,
+            })
+        };
+
+        Self {
+            inner: Rc::new(LocalInner {
+                inbox,
+                tasks: RefCell::new(BTreeMap::new()),
+                next_id: Cell::new(0),
+                running: Cell::new(false),
+            }),
+        }
+    }
+
+    /// Run `fut` on this executor. It is first polled once `block_on` runs.
+    pub fn spawn_local<F>(&self, fut: F) -> LocalJoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        spawn(&self.inner, fut)
+    }
+
+    /// Get a handle that can be moved into tasks to spawn more tasks
+    pub fn spawner(&self) -> LocalSpawner {
+        LocalSpawner {
+            inner: Rc::downgrade(&self.inner),
+        }
+    }
+
+    /// Drive `fut` to completion on the current thread, running the spawned
+    /// tasks and the reactor while it is pending.
+    pub fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        let inner = &*self.inner;
+
+        assert!(
+            !inner.running.replace(true),
+            "LocalExecutor::block_on can not be nested"
+        );
+
+        let mut fut = fut;
+        // Safety: `fut` is shadowed, so it can't be moved anymore
+        let mut fut = unsafe { Pin::new_unchecked(&mut fut) };
+
+        let main_waker = Arc::new(TaskWaker {
+            id: MAIN_TASK,
+            scheduled: AtomicBool::new(false),
+            inbox: inner.inbox.clone(),
+        });
+        let waker = Waker::from(main_waker.clone());
+
+        main_waker.wake_by_ref();
+
+        let mut polled = 0;
+
+        let res = loop {
+            let next = inner.inbox.woken.lock().pop_front();
+
+            match next {
+                Some(MAIN_TASK) => {
+                    main_waker.scheduled.store(false, Ordering::Release);
+
+                    if let Poll::Ready(res) = fut.as_mut().poll(&mut Context::from_waker(&waker)) {
+                        break res;
+                    }
+                }
+                Some(id) => inner.run(id),
+                None => {
+                    let parker = &inner.inbox.parker;
+
+                    // the completions might be what we are about to wait for
+                    URING.submit();
+
+                    parker.sleeping.store(true, Ordering::SeqCst);
+
+                    // A task might have been woken before we announced that we are sleeping
+                    if inner.inbox.woken.lock().is_empty() {
+                        parker.park();
+                    }
+
+                    parker.sleeping.store(false, Ordering::SeqCst);
+
+                    REACTOR.poll();
+
+                    continue;
+                }
+            }
+
+            polled += 1;
+            if polled % REACTOR_POLL_INTERVAL == 0 {
+                REACTOR.poll();
+            }
+        };
+
+        inner.running.set(false);
+
+        res
+    }
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for LocalExecutor {
+    fn drop(&mut self) {
+        // Dropping a task might spawn new ones, which need to be dropped as well
+        loop {
+            let tasks = core::mem::take(&mut *self.inner.tasks.borrow_mut());
+
+            if tasks.is_empty() {
+                break;
+            }
+
+            trace!("dropping {} unfinished local tasks", tasks.len());
+
+            drop(tasks);
+        }
+    }
+}
+
+impl LocalSpawner {
+    /// Run `fut` on the `LocalExecutor` this spawner was created by.
+    /// If the executor was dropped, the task is dropped right away and
+    /// awaiting the handle returns `JoinError::Shutdown`.
+    pub fn spawn_local<F>(&self, fut: F) -> LocalJoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        if let Some(inner) = self.inner.upgrade() {
+            spawn(&inner, fut)
+        } else {
+            let state = Rc::new(RefCell::new(JoinInner::new()));
+            drop(LocalJoined::new(fut, state.clone(), Rc::new(Cell::new(false))));
+
+            LocalJoinHandle {
+                state,
+                task: None,
+            }
+        }
+    }
+}
+
+fn spawn<F>(inner: &LocalInner, fut: F) -> LocalJoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let id = inner.next_id.get();
+    inner.next_id.set(id + 1);
+
+    let state = Rc::new(RefCell::new(JoinInner::new()));
+    let aborted = Rc::new(Cell::new(false));
+
+    let waker = Arc::new(TaskWaker {
+        id,
+        scheduled: AtomicBool::new(false),
+        inbox: inner.inbox.clone(),
+    });
+
+    let task = LocalTask {
+        fut: Box::pin(LocalJoined::new(fut, state.clone(), aborted.clone())),
+        waker: waker.clone(),
+        aborted: aborted.clone(),
+    };
+
+    inner.tasks.borrow_mut().insert(id, task);
+
+    waker.wake_by_ref();
+
+    LocalJoinHandle {
+        state,
+        task: Some((Waker::from(waker), aborted)),
+    }
+}
+
+impl LocalInner {
+    fn run(&self, id: u64) {
+        // Take the task out of the map, so that it can spawn new tasks while it is polled
+        let mut task = if let Some(task) = self.tasks.borrow_mut().remove(&id) {
+            task
+        } else {
+            // The task already finished
+            return;
+        };
+
+        task.waker.scheduled.store(false, Ordering::Release);
+
+        if task.aborted.get() {
+            trace!("dropping an aborted local task");
+
+            return;
+        }
+
+        let waker = Waker::from(task.waker.clone());
+
+        if task
+            .fut
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending()
+        {
+            self.tasks.borrow_mut().insert(id, task);
+        }
+    }
+}
+
+struct JoinInner<T> {
+    result: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+    finished: bool,
+    detached: bool,
+}
+
+impl<T> JoinInner<T> {
+    fn new() -> Self {
+        Self {
+            result: None,
+            waker: None,
+            finished: false,
+            detached: false,
+        }
+    }
+
+    fn complete(&mut self, result: Result<T, JoinError>) -> Option<Waker> {
+        self.finished = true;
+
+        // Nobody is interested in the result, drop it right away
+        if !self.detached {
+            self.result = Some(result);
+        }
+
+        self.waker.take()
+    }
+}
+
+/// Like `join::Joined`, but for tasks that never leave their thread
+struct LocalJoined<F: Future> {
+    fut: F,
+    state: Rc<RefCell<JoinInner<F::Output>>>,
+    aborted: Rc<Cell<bool>>,
+    finished: bool,
+}
+
+impl<F: Future> LocalJoined<F> {
+    fn new(fut: F, state: Rc<RefCell<JoinInner<F::Output>>>, aborted: Rc<Cell<bool>>) -> Self {
+        Self {
+            fut,
+            state,
+            aborted,
+            finished: false,
+        }
+    }
+}
+
+impl<F: Future> Future for LocalJoined<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `fut` is never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+
+        match fut.poll(cx) {
+            Poll::Ready(res) => {
+                this.finished = true;
+
+                let waker = this.state.borrow_mut().complete(Ok(res));
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<F: Future> Drop for LocalJoined<F> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let reason = if self.aborted.get() {
+                JoinError::Aborted
+            } else {
+                JoinError::Shutdown
+            };
+
+            let waker = self.state.borrow_mut().complete(Err(reason));
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A handle to a task spawned on a `LocalExecutor`.
+///
+/// Awaiting it returns the task's output, or the reason it did not finish.
+/// Dropping the handle detaches the task.
+pub struct LocalJoinHandle<T> {
+    state: Rc<RefCell<JoinInner<T>>>,
+    /// `None` if the task was never spawned, because its executor was dropped
+    task: Option<(Waker, Rc<Cell<bool>>)>,
+}
+
+impl<T> LocalJoinHandle<T> {
+    /// Returns true if the task finished or was dropped
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().finished
+    }
+
+    /// Cancel the task. It is dropped the next time it would be polled.
+    /// Awaiting the handle returns `JoinError::Aborted`, unless the task
+    /// already finished.
+    pub fn abort(&self) {
+        if let Some((waker, aborted)) = &self.task {
+            aborted.set(true);
+
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Let the task run to completion in the background, discarding its output
+    pub fn detach(self) {
+        drop(self)
+    }
+}
+
+impl<T> Future for LocalJoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.state.borrow_mut();
+
+        if let Some(result) = inner.result.take() {
+            Poll::Ready(result)
+        } else if inner.finished {
+            panic!("LocalJoinHandle polled after it returned `Ready`");
+        } else {
+            inner.waker = Some(cx.waker().clone());
+
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for LocalJoinHandle<T> {
+    fn drop(&mut self) {
+        let mut inner = self.state.borrow_mut();
+
+        inner.detached = true;
+        inner.waker = None;
+
+        // drop the result outside of the borrow
+        let result = inner.result.take();
+        drop(inner);
+        drop(result);
+    }
+}
+
+/// Drive `fut` to completion on the current thread, without spawning any workers
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    LocalExecutor::new().block_on(fut)
+}
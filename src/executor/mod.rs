@@ -0,0 +1,671 @@
+pub mod channel;
+mod combinators;
+pub mod deque;
+mod join;
+mod local;
+pub mod reactor;
+mod timer;
+pub mod uring;
+
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    mem::ManuallyDrop,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use alloc::{
+    boxed::Box,
+    collections::{LinkedList, VecDeque},
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+
+use crate::{
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+use deque::{Steal, Stealer};
+use join::Joined;
+use reactor::REACTOR;
+
+pub use combinators::{
+    join, join_all, poll_fn, select, FuturesUnordered, JoinAll, MaybeDone, Next, PollFn,
+};
+pub use join::{JoinError, JoinHandle};
+pub use local::{block_on, LocalExecutor, LocalJoinHandle, LocalSpawner};
+pub use timer::{
+    interval, now, sleep, sleep_until, timeout, Elapsed, Interval, Sleep, Tick, Timeout,
+};
+
+/// `None` once the executor has shut down
+type Queue = Pin<Arc<Mutex<Option<VecDeque<Arc<Task>>>>>>;
+
+pub struct Executor {
+    workers: Vec<thread::JoinHandle<()>>,
+    shared: Arc<Shared>,
+    /// All tasks spawned on this executor, so that parked tasks
+    /// can be dropped at shutdown
+    tasks: Pin<Arc<Mutex<Vec<Weak<Task>>>>>,
+}
+
+/// State shared by an `Executor` and its workers
+struct Shared {
+    /// Tasks which were spawned or woken. Workers take batches of
+    /// these into their own deques.
+    injector: Queue,
+    /// One for each worker's queue, so that idle workers can take work from busy ones
+    siblings: Siblings,
+    shutdown: AtomicBool,
+}
+
+/// The queues the executor used before it had work-stealing deques.
+/// Only kept to compare the two designs (see `init_with_locked_queues`).
+type LockedQueue = Pin<Arc<Mutex<LinkedList<Arc<Task>>>>>;
+
+/// A worker's own queue
+enum LocalQueue {
+    Deque(deque::Worker<Task>),
+    Locked(LockedQueue),
+}
+
+/// The other side of every worker's `LocalQueue`
+enum Siblings {
+    Deques(Vec<Stealer<Task>>),
+    Locked(Vec<LockedQueue>),
+}
+
+impl LocalQueue {
+    fn pop(&self) -> Option<Arc<Task>> {
+        match self {
+            LocalQueue::Deque(local) => local.pop(),
+            LocalQueue::Locked(local) => local.lock().pop_front(),
+        }
+    }
+
+    fn extend(&self, tasks: impl Iterator<Item = Arc<Task>>) {
+        match self {
+            LocalQueue::Deque(local) => {
+                for task in tasks {
+                    if local.push(task).is_err() {
+                        unreachable!("deque overflowed while it was refilled");
+                    }
+                }
+            }
+            LocalQueue::Locked(local) => local.lock().extend(tasks),
+        }
+    }
+}
+
+impl Siblings {
+    fn len(&self) -> usize {
+        match self {
+            Siblings::Deques(stealers) => stealers.len(),
+            Siblings::Locked(queues) => queues.len(),
+        }
+    }
+
+    /// Steal half of the tasks of the first sibling after `id` that has any.
+    /// Returns a task to run, the rest is put into `local`.
+    fn steal(&self, id: usize, local: &LocalQueue) -> Option<Arc<Task>> {
+        let n = self.len();
+
+        match (self, local) {
+            (Siblings::Deques(stealers), LocalQueue::Deque(local)) => {
+                for i in (id + 1..n).chain(0..id) {
+                    loop {
+                        match stealers[i].steal_batch(local) {
+                            Steal::Success(work) => return Some(work),
+                            Steal::Retry => continue,
+                            Steal::Empty => break,
+                        }
+                    }
+                }
+            }
+            (Siblings::Locked(queues), LocalQueue::Locked(local)) => {
+                for i in (id + 1..n).chain(0..id) {
+                    let mut queue = queues[i].lock();
+                    let n = queue.len();
+                    let mut stolen = queue.split_off(n / 2);
+                    drop(queue);
+
+                    if let Some(work) = stolen.pop_front() {
+                        local.lock().append(&mut stolen);
+
+                        return Some(work);
+                    }
+                }
+            }
+            _ => unreachable!("worker queue does not match its siblings"),
+        }
+
+        None
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Siblings::Deques(stealers) => stealers.iter().all(|stealer| stealer.is_empty()),
+            Siblings::Locked(queues) => queues.iter().all(|queue| queue.lock().is_empty()),
+        }
+    }
+
+    /// Take all tasks out of the workers' queues
+    fn drain_into(&self, tasks: &mut VecDeque<Arc<Task>>) {
+        match self {
+            Siblings::Deques(stealers) => {
+                for stealer in stealers {
+                    loop {
+                        match stealer.steal() {
+                            Steal::Success(task) => tasks.push_back(task),
+                            Steal::Retry => continue,
+                            Steal::Empty => break,
+                        }
+                    }
+                }
+            }
+            Siblings::Locked(queues) => {
+                for queue in queues {
+                    tasks.extend(core::mem::take(&mut *queue.lock()));
+                }
+            }
+        }
+    }
+}
+
+/// A spawned future together with its scheduling state.
+///
+/// `Task`s are reference counted. While a task is parked (`Pending` and not
+/// woken) the only references to it are held by its `Waker`s.
+struct Task {
+    state: AtomicU32,
+    /// # Safety: only accessed by the worker which moved `state` to `RUNNING`
+    fut: UnsafeCell<Option<Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>>>>,
+    /// The executor's injector, which this task is pushed to when it is woken
+    queue: Queue,
+    /// The task should be dropped instead of being polled
+    aborted: AtomicBool,
+}
+
+// Safety: `fut` is only ever accessed by one worker at a time (see `Task::fut`)
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Task {
+    /// parked, waiting to be woken
+    const IDLE: u32 = 0;
+    /// in a queue, waiting to be polled
+    const SCHEDULED: u32 = 1;
+    /// currently being polled by a worker
+    const RUNNING: u32 = 2;
+    /// woken while it was being polled. Needs to be polled again.
+    const NOTIFIED: u32 = 3;
+    /// the future returned `Ready` or was cancelled and has been dropped
+    const COMPLETE: u32 = 4;
+
+    fn new(fut: Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>>, queue: Queue) -> Self {
+        Self {
+            state: AtomicU32::new(Self::SCHEDULED),
+            fut: UnsafeCell::new(Some(fut)),
+            queue,
+            aborted: AtomicBool::new(false),
+        }
+    }
+
+    /// Drop the future of a task that is not being polled, so that it is never polled again
+    fn cancel(&self) {
+        let mut state = self.state.load(Ordering::Acquire);
+
+        loop {
+            if state != Self::IDLE && state != Self::SCHEDULED {
+                return;
+            }
+
+            match self.state.compare_exchange_weak(
+                state,
+                Self::RUNNING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        // Safety: we moved the task to `RUNNING`, so we have exclusive access to `fut`
+        let fut = unsafe { (*self.fut.get()).take() };
+
+        drop(fut);
+
+        self.state.store(Self::COMPLETE, Ordering::Release);
+    }
+
+    /// Make sure the task is dropped instead of being polled again
+    fn abort(self: Arc<Self>) {
+        self.aborted.store(true, Ordering::Release);
+
+        self.wake();
+    }
+
+    /// Schedule the task to be polled again.
+    /// Does nothing if it is already scheduled or complete.
+    fn wake(self: Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+
+        loop {
+            let new_state = match state {
+                Self::IDLE => Self::SCHEDULED,
+                Self::RUNNING => Self::NOTIFIED,
+                _ => return,
+            };
+
+            match self.state.compare_exchange_weak(
+                state,
+                new_state,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        if state == Self::IDLE {
+            let queue = self.queue.clone();
+
+            inject(&queue, self);
+        }
+    }
+
+    fn waker(self: &Arc<Self>) -> Waker {
+        let ptr = Arc::into_raw(self.clone()) as *const ();
+
+        // Safety: `ptr` holds a strong reference to a `Task`, which `VTABLE` expects
+        unsafe { Waker::from_raw(RawWaker::new(ptr, &VTABLE)) }
+    }
+}
+
+unsafe fn waker_clone(ptr: *const ()) -> RawWaker {
+    Arc::increment_strong_count(ptr as *const Task);
+
+    RawWaker::new(ptr, &VTABLE)
+}
+
+unsafe fn waker_wake(ptr: *const ()) {
+    Arc::from_raw(ptr as *const Task).wake();
+}
+
+unsafe fn waker_wake_by_ref(ptr: *const ()) {
+    // Don't drop the reference owned by the waker
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const Task));
+
+    Arc::clone(&task).wake();
+}
+
+unsafe fn waker_drop(ptr: *const ()) {
+    drop(Arc::from_raw(ptr as *const Task));
+}
+
+static VTABLE: RawWakerVTable =
+    RawWakerVTable::new(waker_clone, waker_wake, waker_wake_by_ref, waker_drop);
+
+/// Push a scheduled task to the injector and wake up a worker to run it.
+/// Returns false, after dropping the task, if the executor has shut down.
+fn inject(injector: &Queue, task: Arc<Task>) -> bool {
+    let mut queue = injector.lock();
+
+    if let Some(queue) = queue.as_mut() {
+        queue.push_back(task);
+    } else {
+        // The executor was dropped, there is nobody left to poll the task
+        trace!("scheduled a task after its executor was dropped");
+
+        drop(queue);
+        task.cancel();
+
+        return false;
+    }
+
+    drop(queue);
+
+    REACTOR.notify();
+
+    true
+}
+
+impl Shared {
+    /// Take a batch of tasks from the injector, or steal half of a sibling's tasks.
+    /// Returns a task to run, the rest of the batch is put into `local`.
+    fn find_work(&self, id: usize, local: &LocalQueue) -> Option<Arc<Task>> {
+        {
+            let mut injector = self.injector.lock();
+            let injector = injector.as_mut()?;
+
+            if let Some(work) = injector.pop_front() {
+                // leave some for the other workers
+                let n = (injector.len() / self.siblings.len()).min(deque::CAPACITY);
+
+                local.extend(injector.drain(..n));
+
+                if n > 0 {
+                    // Let sleeping siblings steal from us
+                    REACTOR.notify();
+                }
+
+                return Some(work);
+            }
+        }
+
+        self.siblings.steal(id, local)
+    }
+
+    /// Check if a worker would find work, or needs to exit
+    fn has_work(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+            || self
+                .injector
+                .lock()
+                .as_ref()
+                .map(|queue| !queue.is_empty())
+                .unwrap_or(true)
+            || !self.siblings.is_empty()
+    }
+}
+
+impl Executor {
+    /// Run `fut` on the executor's workers and block the current thread until it finished
+    pub fn block_on<F>(&self, fut: F) -> F::Output
+    where
+        F: Future + Send + Sync + 'static,
+        F::Output: Send + Sync + 'static,
+    {
+        let mut handle = self.spawn(fut);
+
+        // Safety: The Mutex is `Pin`ned by the `Arc::pin`.
+        let signal = unsafe {
+            Arc::pin(BlockOnSignal {
+                woken: Mutex::new(false),
+                condvar: Condvar::new(),
+            })
+        };
+
+        let waker = Waker::from(Arc::new(BlockOnWaker(signal.clone())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            if let Poll::Ready(res) = Pin::new(&mut handle).poll(&mut cx) {
+                return res.expect("block_on task did not finish");
+            }
+
+            let mut woken = signal
+                .condvar
+                .wait_while(signal.woken.lock(), |woken| !*woken);
+
+            *woken = false;
+        }
+    }
+
+    /// Run `fut` on one of the executor's workers.
+    /// The returned `JoinHandle` can be awaited to get the future's output.
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + Sync + 'static,
+        F::Output: Send + Sync + 'static,
+    {
+        let state = join::join_state();
+
+        let fut = Joined::new(fut, state.clone());
+
+        let task = Arc::new(Task::new(Box::pin(fut), self.shared.injector.clone()));
+
+        {
+            let mut tasks = self.tasks.lock();
+
+            // forget about finished tasks before we need to grow
+            if tasks.len() == tasks.capacity() {
+                tasks.retain(|task| task.strong_count() > 0);
+
+                // If most tasks are still alive, grow anyway. Otherwise
+                // we would have to prune again after a few more spawns.
+                let alive = tasks.len();
+                tasks.reserve(alive);
+            }
+
+            tasks.push(Arc::downgrade(&task));
+        }
+
+        let handle = JoinHandle::new(state, task.clone());
+
+        assert!(
+            inject(&self.shared.injector, task),
+            "spawned a task on an executor that was shut down"
+        );
+
+        handle
+    }
+}
+
+/// Set when the task that `Executor::block_on` is blocked on is woken
+struct BlockOnSignal {
+    woken: Mutex<bool>,
+    condvar: Condvar,
+}
+
+struct BlockOnWaker(Pin<Arc<BlockOnSignal>>);
+
+impl Wake for BlockOnWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        *self.0.woken.lock() = true;
+
+        self.0.condvar.notify_one();
+    }
+}
+
+const WORKER_STACK_SIZE: usize = 1024 * 1024;
+
+/// How many tasks a busy worker polls before checking the reactor for timers and I/O
+const REACTOR_POLL_INTERVAL: usize = 61;
+
+fn worker(id: usize, local: LocalQueue, shared: Arc<Shared>) {
+    let mut polled = 0;
+
+    'work: loop {
+        if shared.shutdown.load(Ordering::Acquire) {
+            break;
+        }
+
+        let work = if let Some(work) = local.pop().or_else(|| shared.find_work(id, &local)) {
+            work
+        } else {
+            // No work. Wait for new work to be added or for I/O events
+
+            REACTOR.prepare_wait();
+
+            // Work might have been added before we announced that we are waiting
+            if shared.has_work() {
+                REACTOR.cancel_wait();
+            } else {
+                REACTOR.wait();
+            }
+
+            continue;
+        };
+
+        trace!("worker got work");
+
+        // Workers only block on the reactor when they run out of work,
+        // so a busy worker has to check for expired timers and I/O itself
+        polled += 1;
+        if polled % REACTOR_POLL_INTERVAL == 0 {
+            REACTOR.poll();
+        }
+
+        if work.aborted.load(Ordering::Acquire) {
+            trace!("dropping an aborted task");
+
+            work.cancel();
+            continue;
+        }
+
+        if work
+            .state
+            .compare_exchange(
+                Task::SCHEDULED,
+                Task::RUNNING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            // Only `SCHEDULED` tasks are put into queues
+            unreachable!("tried to run a task that was not scheduled");
+        }
+
+        let waker = work.waker();
+        let mut context = Context::from_waker(&waker);
+
+        trace!("polling");
+
+        // Safety: we moved the task to `RUNNING`, so we have exclusive access to `fut`
+        let fut = unsafe { &mut *work.fut.get() };
+
+        let res = fut
+            .as_mut()
+            .expect("scheduled task has no future")
+            .as_mut()
+            .poll(&mut context);
+
+        match res {
+            Poll::Ready(()) => {
+                trace!("finished an async task");
+
+                *fut = None;
+                work.state.store(Task::COMPLETE, Ordering::Release);
+            }
+            Poll::Pending => {
+                // Park the task. If it was woken while we were polling it,
+                // it needs to be polled again.
+                if work
+                    .state
+                    .compare_exchange(
+                        Task::RUNNING,
+                        Task::IDLE,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    )
+                    .is_err()
+                {
+                    work.state.store(Task::SCHEDULED, Ordering::Release);
+
+                    // Don't put it into our own deque. We would pop it again right
+                    // away, which would starve the other tasks if it keeps yielding.
+                    if !inject(&shared.injector, work.clone()) {
+                        break 'work;
+                    }
+                }
+            }
+        }
+    }
+
+    // The executor is shutting down, make sure the other workers notice
+    REACTOR.notify();
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+
+        let mut unfinished = self.shared.injector.lock().take().unwrap_or_default();
+
+        // Wake up sleeping workers so they notice the shutdown
+        REACTOR.notify();
+
+        for mut worker in self.workers.drain(..) {
+            worker.join().expect("Failed to join worker thread");
+        }
+
+        // Take the tasks that were left in the workers' queues
+        self.shared.siblings.drain_into(&mut unfinished);
+
+        // Drop all remaining tasks, including queued and parked ones.
+        // Their `JoinHandle`s report `JoinError::Shutdown`.
+        let tasks = core::mem::take(&mut *self.tasks.lock());
+
+        let mut n_unfinished = 0;
+        for task in tasks.iter().filter_map(Weak::upgrade) {
+            if task.state.load(Ordering::Acquire) != Task::COMPLETE {
+                n_unfinished += 1;
+            }
+
+            task.cancel();
+        }
+
+        if n_unfinished != 0 {
+            debug!("dropped {} unfinished tasks", n_unfinished);
+        }
+
+        drop(unfinished);
+    }
+}
+
+pub fn init(n_threads: usize) -> Executor {
+    let (locals, stealers): (Vec<_>, Vec<_>) = (0..n_threads)
+        .map(|_| {
+            let (local, stealer) = deque::new();
+            (LocalQueue::Deque(local), stealer)
+        })
+        .unzip();
+
+    start(locals, Siblings::Deques(stealers))
+}
+
+/// Like `init`, but the workers use `Mutex<LinkedList>` queues and steal with
+/// `split_off`, like the executor did before it had work-stealing deques.
+/// This is only useful to compare the two designs.
+pub fn init_with_locked_queues(n_threads: usize) -> Executor {
+    let (locals, queues): (Vec<_>, Vec<_>) = (0..n_threads)
+        .map(|_| {
+            // Safety: the Arc `Pin`s the Mutex
+            let queue: LockedQueue = unsafe { Arc::pin(Mutex::new(LinkedList::new())) };
+            (LocalQueue::Locked(queue.clone()), queue)
+        })
+        .unzip();
+
+    start(locals, Siblings::Locked(queues))
+}
+
+fn start(locals: Vec<LocalQueue>, siblings: Siblings) -> Executor {
+    let shared = Arc::new(Shared {
+        // Safety: the Arc `Pin`s the Mutex
+        injector: unsafe { Arc::pin(Mutex::new(Some(VecDeque::new()))) },
+        siblings,
+        shutdown: AtomicBool::new(false),
+    });
+
+    let workers = locals
+        .into_iter()
+        .enumerate()
+        .map(|(i, local)| {
+            let shared = shared.clone();
+
+            thread::spawn(move || worker(i, local, shared), Some(WORKER_STACK_SIZE))
+                .expect("Failed to spawn worker thread")
+        })
+        .collect();
+
+    // Safety: the Arc `Pin`s the Mutex
+    let tasks = unsafe { Arc::pin(Mutex::new(Vec::new())) };
+
+    Executor {
+        workers,
+        shared,
+        tasks,
+    }
+}
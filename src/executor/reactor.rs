@@ -0,0 +1,497 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+use crate::{
+    io::Fd,
+    sync::Mutex,
+    syscalls::{
+        self, epoll_create1, epoll_ctl, epoll_wait, eventfd2, helper::SyscallErrorKind,
+        EpollCreateFlags, EpollEvent, EpollFlags, EpollOp, EventFdFlags, SyscallResult,
+    },
+};
+
+use super::{timer::TIMERS, uring::URING};
+
+/// Maximum number of events handled per call to `epoll_wait`
+const MAX_EVENTS: usize = 64;
+
+/// The epoll token used for the `eventfd` which wakes sleeping workers
+const NOTIFY_TOKEN: u64 = 0;
+
+/// The epoll token used for the timerfd of `timer::TIMERS`
+pub(super) const TIMER_TOKEN: u64 = 1;
+
+/// The epoll token used for the io_uring of `uring::URING`
+pub(super) const URING_TOKEN: u64 = 2;
+
+const UNINITIALIZED: u32 = u32::MAX;
+
+/// The process wide I/O reactor
+///
+/// # Safety
+/// `Pin`ed, since it is a static
+pub(crate) static REACTOR: Reactor = unsafe { Reactor::new() };
+
+bitflags::bitflags! {
+    pub struct Interest: u32 {
+        const READABLE = 1;
+        const WRITABLE = 2;
+    }
+}
+
+/// Number of bits at the bottom of `Source::readiness` used for `Interest`.
+/// The remaining bits count the events received for the source.
+const TICK_SHIFT: u32 = 8;
+const READINESS_MASK: u32 = (1 << TICK_SHIFT) - 1;
+
+/// A snapshot of the readiness of a `Registration`.
+///
+/// Pass it to `Registration::clear_readiness` once an operation returned `EAGAIN`.
+#[derive(Debug, Clone, Copy)]
+pub struct Readiness {
+    interest: Interest,
+    tick: u32,
+}
+
+struct Source {
+    fd: Fd,
+    readiness: AtomicU32,
+    /// All tasks waiting for the fd, since several tasks can share a `Registration`
+    read_wakers: Mutex<Vec<Waker>>,
+    write_wakers: Mutex<Vec<Waker>>,
+}
+
+impl Source {
+    /// Mark the source as ready and wake the tasks waiting for it
+    fn set_ready(&self, interest: Interest) {
+        let _ = self
+            .readiness
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+                let tick = (state >> TICK_SHIFT).wrapping_add(1);
+
+                Some((tick << TICK_SHIFT) | (state & READINESS_MASK) | interest.bits())
+            });
+
+        if interest.contains(Interest::READABLE) {
+            Self::wake_all(&self.read_wakers);
+        }
+
+        if interest.contains(Interest::WRITABLE) {
+            Self::wake_all(&self.write_wakers);
+        }
+    }
+
+    fn wake_all(wakers: &Mutex<Vec<Waker>>) {
+        let wakers = core::mem::take(&mut *wakers.lock());
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Add `waker` to `wakers`, unless it would wake the same task as one already in there
+    fn add_waker(wakers: &Mutex<Vec<Waker>>, waker: &Waker) {
+        let mut wakers = wakers.lock();
+
+        if !wakers.iter().any(|other| other.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+/// Waits for I/O events using epoll and wakes the tasks waiting for them.
+/// Idle executor workers block in `Reactor::wait`.
+pub(crate) struct Reactor {
+    epoll: AtomicU32,
+    event_fd: AtomicU32,
+    /// Number of workers blocked in `epoll_wait`
+    sleeping: AtomicU32,
+    next_token: AtomicU64,
+    sources: Mutex<BTreeMap<u64, Pin<Arc<Source>>>>,
+}
+
+impl Reactor {
+    /// # Safety: must be pinned
+    const unsafe fn new() -> Self {
+        Self {
+            epoll: AtomicU32::new(UNINITIALIZED),
+            event_fd: AtomicU32::new(UNINITIALIZED),
+            sleeping: AtomicU32::new(0),
+            next_token: AtomicU64::new(URING_TOKEN + 1),
+            sources: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Get the epoll fd, creating it on first use
+    pub(super) fn epoll(&self) -> SyscallResult<u32> {
+        let epoll = self.epoll.load(Ordering::Acquire);
+        if epoll != UNINITIALIZED {
+            return Ok(epoll);
+        }
+
+        // use the sources lock to make sure we only initialize once
+        let _sources = self.sources.lock();
+
+        let epoll = self.epoll.load(Ordering::Acquire);
+        if epoll != UNINITIALIZED {
+            return Ok(epoll);
+        }
+
+        unsafe {
+            let epoll = epoll_create1(EpollCreateFlags::CLOEXEC)?;
+
+            let event_fd =
+                eventfd2(0, EventFdFlags::CLOEXEC | EventFdFlags::NONBLOCK).map_err(|err| {
+                    syscalls::close(Fd(epoll)).expect("Failed to close epoll");
+                    err
+                })?;
+
+            let mut event = EpollEvent {
+                events: EpollFlags::IN,
+                data: NOTIFY_TOKEN,
+            };
+            epoll_ctl(epoll, EpollOp::Add, event_fd, &mut event as *mut _).map_err(|err| {
+                syscalls::close(Fd(event_fd)).expect("Failed to close eventfd");
+                syscalls::close(Fd(epoll)).expect("Failed to close epoll");
+                err
+            })?;
+
+            self.event_fd.store(event_fd, Ordering::Release);
+            self.epoll.store(epoll, Ordering::Release);
+
+            Ok(epoll)
+        }
+    }
+
+    /// Wake up a worker sleeping in `wait`, if there are any.
+    pub(crate) fn notify(&self) {
+        if self.sleeping.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        let event_fd = self.event_fd.load(Ordering::Acquire);
+        debug_assert_ne!(event_fd, UNINITIALIZED);
+
+        match syscalls::write(event_fd, &1u64.to_ne_bytes()) {
+            // The counter is about to overflow, so there is already a pending notification
+            Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {}
+            res => {
+                res.expect("Failed to notify reactor");
+            }
+        }
+    }
+
+    /// Announce that the calling worker is about to `wait`.
+    ///
+    /// Any work queued after this call will `notify` the reactor, so the worker
+    /// has to check for new work once more before it calls `wait`.
+    pub(crate) fn prepare_wait(&self) {
+        // make sure `event_fd` is set up before anyone tries to notify us
+        self.epoll().expect("Failed to set up reactor");
+
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Undo a `prepare_wait` without waiting
+    pub(crate) fn cancel_wait(&self) {
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Block until an I/O event occurs or `notify` is called
+    /// and wake the tasks waiting for the events.
+    /// Must be preceded by a call to `prepare_wait`.
+    pub(crate) fn wait(&self) {
+        self.poll_events(-1);
+
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wake the tasks waiting for I/O events that have already occurred,
+    /// without blocking
+    pub(crate) fn poll(&self) {
+        self.poll_events(0);
+    }
+
+    fn poll_events(&self, timeout: i32) {
+        let epoll = self.epoll().expect("Failed to set up reactor");
+
+        // the completions might be what we are about to wait for
+        URING.submit();
+
+        let mut events = [EpollEvent::empty(); MAX_EVENTS];
+
+        let res = unsafe { epoll_wait(epoll, events.as_mut_ptr(), MAX_EVENTS as i32, timeout) };
+
+        let n = match res {
+            Ok(n) => n,
+            Err(err) if err.kind() == SyscallErrorKind::EINTR => return,
+            Err(err) => panic!("Failed to wait for I/O events: {}", err),
+        };
+
+        for event in &events[..n] {
+            let token = event.data;
+            let flags = event.events;
+
+            if token == NOTIFY_TOKEN {
+                // Only sleepers reset the counter. Otherwise a thread that is
+                // just polling could swallow the notification meant for them.
+                if timeout == 0 {
+                    continue;
+                }
+
+                // reset the event counter
+                let mut buf = [0; 8];
+                match syscalls::read(self.event_fd.load(Ordering::Acquire), &mut buf) {
+                    // Another worker already reset it
+                    Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {}
+                    res => {
+                        res.expect("Failed to read from eventfd");
+                    }
+                }
+
+                continue;
+            }
+
+            if token == TIMER_TOKEN {
+                TIMERS.fire();
+
+                continue;
+            }
+
+            if token == URING_TOKEN {
+                URING.complete();
+
+                continue;
+            }
+
+            let source = if let Some(source) = self.sources.lock().get(&token) {
+                source.clone()
+            } else {
+                // the source was deregistered while we were waiting
+                continue;
+            };
+
+            let mut interest = Interest::empty();
+
+            // errors and hang ups are reported by the next read or write
+            let error = EpollFlags::ERR | EpollFlags::HUP;
+
+            if flags.intersects(EpollFlags::IN | EpollFlags::PRI | EpollFlags::RDHUP | error) {
+                interest |= Interest::READABLE;
+            }
+            if flags.intersects(EpollFlags::OUT | error) {
+                interest |= Interest::WRITABLE;
+            }
+
+            source.set_ready(interest);
+        }
+    }
+
+    /// Register the fd of a driver like `timer::TIMERS`,
+    /// whose events are handled by the reactor itself
+    pub(super) fn register_driver(&self, fd: Fd, token: u64) -> SyscallResult<()> {
+        let epoll = self.epoll()?;
+
+        let mut event = EpollEvent {
+            events: EpollFlags::IN,
+            data: token,
+        };
+
+        unsafe { epoll_ctl(epoll, EpollOp::Add, fd.0, &mut event as *mut _) }
+    }
+
+    fn register(&self, fd: Fd) -> SyscallResult<(u64, Pin<Arc<Source>>)> {
+        let epoll = self.epoll()?;
+
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+
+        // Safety: the Mutexes are `Pin`ed by the `Arc::pin`
+        let source = unsafe {
+            Arc::pin(Source {
+                fd,
+                // Assume the fd is ready until an operation returns `EAGAIN`
+                readiness: AtomicU32::new((Interest::READABLE | Interest::WRITABLE).bits()),
+                read_wakers: Mutex::new(Vec::new()),
+                write_wakers: Mutex::new(Vec::new()),
+            })
+        };
+
+        self.sources.lock().insert(token, source.clone());
+
+        let mut event = EpollEvent {
+            events: EpollFlags::IN | EpollFlags::OUT | EpollFlags::RDHUP | EpollFlags::ET,
+            data: token,
+        };
+
+        if let Err(err) = unsafe { epoll_ctl(epoll, EpollOp::Add, fd.0, &mut event as *mut _) } {
+            self.sources.lock().remove(&token);
+
+            return Err(err);
+        }
+
+        Ok((token, source))
+    }
+
+    fn deregister(&self, token: u64) {
+        let source = {
+            let mut sources = self.sources.lock();
+
+            let source = sources.remove(&token);
+
+            if sources.is_empty() {
+                // free the map's nodes, so that they are not reported as leaked at exit
+                *sources = BTreeMap::new();
+            }
+
+            source
+        };
+
+        if let Some(source) = source {
+            let epoll = self.epoll.load(Ordering::Acquire);
+
+            let res = unsafe {
+                epoll_ctl(
+                    epoll,
+                    EpollOp::Del,
+                    source.fd.0,
+                    core::ptr::null_mut(),
+                )
+            };
+
+            // Closing an fd removes it from the epoll set
+            if let Err(err) = res {
+                if err.kind() != SyscallErrorKind::EBADF {
+                    panic!("Failed to deregister fd {}: {}", source.fd.0, err);
+                }
+            }
+        }
+    }
+}
+
+/// An fd registered with the reactor.
+///
+/// The fd is expected to be in non-blocking mode (`OpenFlags::NONBLOCK`).
+/// It is deregistered once the `Registration` is dropped, but not closed.
+pub struct Registration {
+    token: u64,
+    source: Pin<Arc<Source>>,
+}
+
+impl Registration {
+    pub fn new(fd: Fd) -> SyscallResult<Self> {
+        let (token, source) = REACTOR.register(fd)?;
+
+        Ok(Self { token, source })
+    }
+
+    /// Get the registered fd.
+    pub fn fd(&self) -> Fd {
+        self.source.fd
+    }
+
+    /// Poll until the fd is ready for any of `interest`.
+    pub fn poll_ready(&self, interest: Interest, cx: &mut Context<'_>) -> Poll<Readiness> {
+        let ready = |state: u32| {
+            let ready = Interest::from_bits_truncate(state & READINESS_MASK) & interest;
+
+            (!ready.is_empty()).then(|| Readiness {
+                interest: ready,
+                tick: state >> TICK_SHIFT,
+            })
+        };
+
+        if let Some(readiness) = ready(self.source.readiness.load(Ordering::Acquire)) {
+            return Poll::Ready(readiness);
+        }
+
+        if interest.contains(Interest::READABLE) {
+            Source::add_waker(&self.source.read_wakers, cx.waker());
+        }
+        if interest.contains(Interest::WRITABLE) {
+            Source::add_waker(&self.source.write_wakers, cx.waker());
+        }
+
+        // The fd might have become ready before we registered our waker
+        match ready(self.source.readiness.load(Ordering::Acquire)) {
+            Some(readiness) => Poll::Ready(readiness),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Mark the fd as not ready, unless a new event arrived since `readiness`
+    /// was returned by `poll_ready`.
+    pub fn clear_readiness(&self, readiness: Readiness) {
+        let _ = self
+            .source
+            .readiness
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+                (state >> TICK_SHIFT == readiness.tick)
+                    .then(|| state & !readiness.interest.bits())
+            });
+    }
+
+    /// Retry `f` until it does not fail with `EAGAIN`,
+    /// waiting for the fd to become ready for `interest` in between.
+    pub fn poll_io<T>(
+        &self,
+        interest: Interest,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut() -> SyscallResult<T>,
+    ) -> Poll<SyscallResult<T>> {
+        loop {
+            let readiness = match self.poll_ready(interest, cx) {
+                Poll::Ready(readiness) => readiness,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match f() {
+                Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {
+                    self.clear_readiness(readiness)
+                }
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    /// Wait until the fd is readable
+    pub fn readable(&self) -> Ready<'_> {
+        Ready {
+            registration: self,
+            interest: Interest::READABLE,
+        }
+    }
+
+    /// Wait until the fd is writable
+    pub fn writable(&self) -> Ready<'_> {
+        Ready {
+            registration: self,
+            interest: Interest::WRITABLE,
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        REACTOR.deregister(self.token);
+    }
+}
+
+/// Future returned by `Registration::readable` and `Registration::writable`
+pub struct Ready<'r> {
+    registration: &'r Registration,
+    interest: Interest,
+}
+
+impl Future for Ready<'_> {
+    type Output = Readiness;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.registration.poll_ready(self.interest, cx)
+    }
+}
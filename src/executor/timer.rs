@@ -0,0 +1,343 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use alloc::collections::BTreeMap;
+
+use crate::{
+    io::Fd,
+    sync::Mutex,
+    syscalls::{
+        self, helper::SyscallErrorKind, timerfd_create, timerfd_settime, ClockId, ItimerSpec,
+        SyscallResult, TimerFdFlags, TimerFdSetFlags, Timespec,
+    },
+};
+
+use super::reactor::{REACTOR, TIMER_TOKEN};
+
+/// The clock all deadlines are measured with
+const CLOCK: ClockId = ClockId::Monotonic;
+
+/// The process wide timer driver
+///
+/// # Safety
+/// `Pin`ed, since it is a static
+pub(super) static TIMERS: Timers = unsafe { Timers::new() };
+
+/// The current time of the clock used for timers
+pub fn now() -> Duration {
+    syscalls::clock_gettime(CLOCK).expect("Failed to read the monotonic clock")
+}
+
+struct TimersInner {
+    /// Created on first use
+    timer_fd: Option<Fd>,
+    /// The deadline the timerfd is currently set to
+    armed: Option<Duration>,
+    /// Pending timers, ordered by their deadline
+    timers: BTreeMap<(Duration, u64), Waker>,
+}
+
+/// Keeps track of all pending timers and wakes them using a single timerfd,
+/// which is registered with the reactor.
+pub(super) struct Timers {
+    next_id: AtomicU64,
+    inner: Mutex<TimersInner>,
+}
+
+impl Timers {
+    /// # Safety: must be pinned
+    const unsafe fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            inner: Mutex::new(TimersInner {
+                timer_fd: None,
+                armed: None,
+                timers: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Wake `waker` once `deadline` has passed.
+    /// Replaces the waker previously registered with the same `deadline` and `id`.
+    fn register(&self, deadline: Duration, id: u64, waker: &Waker) -> SyscallResult<()> {
+        let mut inner = self.inner.lock();
+
+        inner.timers.insert((deadline, id), waker.clone());
+
+        if inner.armed.map(|armed| deadline < armed).unwrap_or(true) {
+            inner.arm(Some(deadline))?;
+        }
+
+        Ok(())
+    }
+
+    fn deregister(&self, deadline: Duration, id: u64) {
+        let mut inner = self.inner.lock();
+
+        inner.timers.remove(&(deadline, id));
+
+        if inner.timers.is_empty() {
+            // free the map's nodes, so that they are not reported as leaked at exit
+            inner.timers = BTreeMap::new();
+        }
+
+        // NOTE: we don't disarm the timerfd here. If it fires, there is just nothing to wake.
+    }
+
+    /// Wake all timers whose deadline has passed.
+    /// Called by the reactor when the timerfd fires.
+    pub(super) fn fire(&self) {
+        let mut inner = self.inner.lock();
+
+        if let Some(timer_fd) = inner.timer_fd {
+            // reset the expiration counter
+            let mut buf = [0; 8];
+            match syscalls::read(timer_fd.0, &mut buf) {
+                // Another worker already handled the expiration
+                Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {}
+                res => {
+                    res.expect("Failed to read from timerfd");
+                }
+            }
+        }
+
+        let now = now();
+
+        let pending = inner.timers.split_off(&(now, u64::MAX));
+        let expired = core::mem::replace(&mut inner.timers, pending);
+
+        let next = inner.timers.keys().next().map(|&(deadline, _)| deadline);
+        inner.arm(next).expect("Failed to arm timerfd");
+
+        if inner.timers.is_empty() {
+            inner.timers = BTreeMap::new();
+        }
+
+        drop(inner);
+
+        for (_, waker) in expired {
+            waker.wake();
+        }
+    }
+}
+
+impl TimersInner {
+    /// Set the timerfd to fire at `deadline`, or disarm it
+    fn arm(&mut self, deadline: Option<Duration>) -> SyscallResult<()> {
+        let timer_fd = match self.timer_fd {
+            Some(timer_fd) => timer_fd,
+            None => {
+                let timer_fd = unsafe {
+                    timerfd_create(CLOCK, TimerFdFlags::NONBLOCK | TimerFdFlags::CLOEXEC)
+                }
+                .map(Fd)?;
+
+                REACTOR.register_driver(timer_fd, TIMER_TOKEN)?;
+
+                self.timer_fd = Some(timer_fd);
+
+                timer_fd
+            }
+        };
+
+        let spec = ItimerSpec {
+            interval: Timespec::default(),
+            // a zero value disarms the timer
+            value: deadline.map(Timespec::from).unwrap_or_default(),
+        };
+
+        unsafe {
+            timerfd_settime(
+                timer_fd.0,
+                TimerFdSetFlags::ABSTIME,
+                &spec as *const _,
+                core::ptr::null_mut(),
+            )?;
+        }
+
+        self.armed = deadline;
+
+        Ok(())
+    }
+}
+
+/// Future returned by `sleep` and `sleep_until`
+pub struct Sleep {
+    deadline: Duration,
+    id: u64,
+    registered: bool,
+}
+
+impl Sleep {
+    /// The time at which the `Sleep` completes
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    /// Change the deadline without creating a new `Sleep`
+    pub fn reset(&mut self, deadline: Duration) {
+        if self.registered {
+            TIMERS.deregister(self.deadline, self.id);
+            self.registered = false;
+        }
+
+        self.deadline = deadline;
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if now() >= self.deadline {
+            if self.registered {
+                TIMERS.deregister(self.deadline, self.id);
+                self.registered = false;
+            }
+
+            return Poll::Ready(());
+        }
+
+        TIMERS
+            .register(self.deadline, self.id, cx.waker())
+            .expect("Failed to register timer");
+        self.registered = true;
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if self.registered {
+            TIMERS.deregister(self.deadline, self.id);
+        }
+    }
+}
+
+/// Wait until `duration` has passed without blocking the worker
+pub fn sleep(duration: Duration) -> Sleep {
+    // A deadline that can't be represented is never reached
+    sleep_until(now().checked_add(duration).unwrap_or(Duration::MAX))
+}
+
+/// Wait until `now()` reaches `deadline`
+pub fn sleep_until(deadline: Duration) -> Sleep {
+    Sleep {
+        deadline,
+        id: TIMERS.next_id(),
+        registered: false,
+    }
+}
+
+/// Returned by `Timeout` if the future did not complete in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl core::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+/// Future returned by `timeout`
+pub struct Timeout<F> {
+    fut: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `fut` is never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+
+        if let Poll::Ready(res) = fut.poll(cx) {
+            return Poll::Ready(Ok(res));
+        }
+
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Run `fut`, but give up if it did not complete after `duration`
+pub fn timeout<F: Future>(fut: F, duration: Duration) -> Timeout<F> {
+    Timeout {
+        fut,
+        sleep: sleep(duration),
+    }
+}
+
+/// Ticks once every `period`. Created by `interval`
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+}
+
+impl Interval {
+    /// Poll for the next tick.
+    ///
+    /// If ticks were missed, because the interval was not polled in time,
+    /// they are skipped and the next tick is scheduled `period` from now.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Duration> {
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => {
+                let tick = self.sleep.deadline();
+
+                let mut next = tick.checked_add(self.period).unwrap_or(Duration::MAX);
+
+                let now = now();
+                if next < now {
+                    next = now.checked_add(self.period).unwrap_or(Duration::MAX);
+                }
+
+                self.sleep.reset(next);
+
+                Poll::Ready(tick)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Wait for the next tick. Returns the time the tick was scheduled for.
+    pub fn tick(&mut self) -> Tick<'_> {
+        Tick { interval: self }
+    }
+}
+
+/// Future returned by `Interval::tick`
+pub struct Tick<'i> {
+    interval: &'i mut Interval,
+}
+
+impl Future for Tick<'_> {
+    type Output = Duration;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.interval.poll_tick(cx)
+    }
+}
+
+/// Create an `Interval` that ticks every `period`, starting immediately
+pub fn interval(period: Duration) -> Interval {
+    assert!(period > Duration::ZERO, "interval period must be non-zero");
+
+    Interval {
+        sleep: sleep_until(now()),
+        period,
+    }
+}
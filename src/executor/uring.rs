@@ -0,0 +1,358 @@
+//! Completion based I/O on a process wide io_uring.
+//!
+//! An alternative to waiting for readiness with `reactor::Registration`:
+//! operations are queued on the ring when they are created, and their task is
+//! woken once the kernel completed them. Operations created in a row are
+//! submitted together, the next time the executor polls the reactor or runs
+//! out of work, so a task can start many reads at once by creating them
+//! before awaiting any of them.
+
+use core::{
+    any::Any,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+use crate::{
+    io::Fd,
+    io_uring::{IoUring, QueueFull},
+    sync::Mutex,
+    syscalls::{helper::SyscallErrorKind, IoUringSqe, SyscallError, SyscallResult},
+};
+
+use super::reactor::{REACTOR, URING_TOKEN};
+
+/// Size of the submission queue
+const ENTRIES: u32 = 256;
+
+/// The process wide io_uring driver
+///
+/// # Safety
+/// `Pin`ed, since it is a static
+pub(super) static URING: Driver = unsafe { Driver::new() };
+
+enum State {
+    /// Not completed yet. Holds the waker of the last poll.
+    Pending(Option<Waker>),
+    Completed(i32),
+    /// The `Op` was dropped. Keeps its resources alive until the kernel is done with them.
+    Abandoned(Box<dyn Any + Send + Sync>),
+}
+
+struct DriverInner {
+    /// Created on first use
+    ring: Option<IoUring>,
+    next_id: u64,
+    ops: BTreeMap<u64, State>,
+}
+
+pub(super) struct Driver {
+    /// Whether entries were pushed since the last submit
+    unsubmitted: AtomicBool,
+    inner: Mutex<DriverInner>,
+}
+
+impl Driver {
+    /// # Safety: must be pinned
+    const unsafe fn new() -> Self {
+        Self {
+            unsubmitted: AtomicBool::new(false),
+            inner: Mutex::new(DriverInner {
+                ring: None,
+                next_id: 0,
+                ops: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Queue `sqe` for submission and return the id of its operation
+    fn push(&self, sqe: IoUringSqe) -> SyscallResult<u64> {
+        let mut inner = self.inner.lock();
+
+        let id = inner.next_id;
+        let sqe = sqe.user_data(id);
+
+        let mut woken = Vec::new();
+
+        loop {
+            let ring = inner.ring()?;
+
+            // Safety: the `Op` for this entry keeps its resources alive until it completed
+            match unsafe { ring.push(&sqe) } {
+                Ok(()) => break,
+                Err(QueueFull) => match ring.submit() {
+                    Ok(_) => {}
+                    // The completion queue is full, make space for more completions
+                    Err(err) if err.kind() == SyscallErrorKind::EBUSY => inner.reap(&mut woken),
+                    Err(err) if err.kind() == SyscallErrorKind::EINTR => {}
+                    Err(err) => return Err(err),
+                },
+            }
+        }
+
+        inner.next_id += 1;
+        inner.ops.insert(id, State::Pending(None));
+
+        self.unsubmitted.store(true, Ordering::Release);
+
+        drop(inner);
+
+        for waker in woken {
+            waker.wake();
+        }
+
+        Ok(id)
+    }
+
+    /// Hand the queued entries to the kernel.
+    /// Called by the reactor before it waits for events.
+    pub(super) fn submit(&self) {
+        if !self.unsubmitted.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let mut inner = self.inner.lock();
+
+        let mut woken = Vec::new();
+
+        if let Some(ring) = inner.ring.as_mut() {
+            match ring.submit() {
+                Ok(_) => {}
+                Err(err)
+                    if err.kind() == SyscallErrorKind::EBUSY
+                        || err.kind() == SyscallErrorKind::EAGAIN
+                        || err.kind() == SyscallErrorKind::EINTR =>
+                {
+                    // try again the next time
+                    self.unsubmitted.store(true, Ordering::Release);
+
+                    inner.reap(&mut woken);
+                }
+                Err(err) => panic!("Failed to submit to io_uring: {}", err),
+            }
+        }
+
+        drop(inner);
+
+        for waker in woken {
+            waker.wake();
+        }
+    }
+
+    /// Wake the tasks whose operations completed.
+    /// Called by the reactor when the ring's fd is readable.
+    pub(super) fn complete(&self) {
+        let mut woken = Vec::new();
+
+        self.inner.lock().reap(&mut woken);
+
+        for waker in woken {
+            waker.wake();
+        }
+    }
+
+    fn poll_op(&self, id: u64, cx: &mut Context<'_>) -> Poll<SyscallResult<u32>> {
+        let mut inner = self.inner.lock();
+
+        match inner.ops.get_mut(&id) {
+            Some(State::Pending(waker)) => {
+                *waker = Some(cx.waker().clone());
+
+                Poll::Pending
+            }
+            Some(State::Completed(res)) => {
+                let res = *res;
+                inner.remove(id);
+
+                Poll::Ready(if res < 0 {
+                    Err(SyscallError(-res as u32))
+                } else {
+                    Ok(res as u32)
+                })
+            }
+            _ => unreachable!("polled an abandoned io_uring operation"),
+        }
+    }
+
+    /// Forget about the operation `id`, dropping `data` once it completed
+    fn abandon(&self, id: u64, data: Box<dyn Any + Send + Sync>) {
+        let mut inner = self.inner.lock();
+
+        let completed = matches!(inner.ops.get(&id), Some(State::Completed(_)));
+
+        if completed {
+            inner.remove(id);
+        } else {
+            inner.ops.insert(id, State::Abandoned(data));
+        }
+    }
+}
+
+impl DriverInner {
+    fn ring(&mut self) -> SyscallResult<&mut IoUring> {
+        if self.ring.is_none() {
+            let ring = IoUring::new(ENTRIES)?;
+
+            REACTOR.register_driver(ring.fd(), URING_TOKEN)?;
+
+            self.ring = Some(ring);
+        }
+
+        Ok(self.ring.as_mut().unwrap())
+    }
+
+    /// Take all completions from the ring and collect the wakers of their tasks
+    fn reap(&mut self, woken: &mut Vec<Waker>) {
+        while let Some(cqe) = self.ring.as_mut().and_then(IoUring::pop) {
+            let id = cqe.user_data;
+
+            match self.ops.insert(id, State::Completed(cqe.res)) {
+                Some(State::Pending(waker)) => woken.extend(waker),
+                Some(State::Abandoned(_)) => self.remove(id),
+                Some(State::Completed(_)) | None => {
+                    unreachable!("io_uring operation {} completed twice", id)
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.ops.remove(&id);
+
+        if self.ops.is_empty() {
+            // free the map's nodes, so that they are not reported as leaked at exit
+            self.ops = BTreeMap::new();
+        }
+    }
+}
+
+/// The resources an operation keeps alive until the kernel completed it,
+/// and how they are turned into its output
+pub trait Completion: Send + Sync + Unpin + 'static {
+    type Output;
+
+    fn complete(self, res: SyscallResult<u32>) -> Self::Output;
+}
+
+impl Completion for () {
+    type Output = SyscallResult<()>;
+
+    fn complete(self, res: SyscallResult<u32>) -> Self::Output {
+        res.map(|_| ())
+    }
+}
+
+/// The buffer of a `read_at`
+pub struct ReadBuf(Vec<u8>);
+
+impl Completion for ReadBuf {
+    /// The number of bytes read, which is 0 at the end of the file, and the buffer
+    type Output = (SyscallResult<usize>, Vec<u8>);
+
+    fn complete(self, res: SyscallResult<u32>) -> Self::Output {
+        let mut buf = self.0;
+
+        if let Ok(n) = res {
+            // Safety: the kernel initialized `n` bytes of the spare capacity
+            unsafe { buf.set_len(buf.len() + n as usize) };
+        }
+
+        (res.map(|n| n as usize), buf)
+    }
+}
+
+/// The buffer of a `write_at`
+pub struct WriteBuf(Vec<u8>);
+
+impl Completion for WriteBuf {
+    /// The number of bytes written and the buffer
+    type Output = (SyscallResult<usize>, Vec<u8>);
+
+    fn complete(self, res: SyscallResult<u32>) -> Self::Output {
+        (res.map(|n| n as usize), self.0)
+    }
+}
+
+/// An operation queued on the io_uring. Completes with `T::Output`.
+///
+/// If it is dropped before it completed, the operation still runs to
+/// completion, but its result is discarded.
+pub struct Op<T: Completion> {
+    /// The error if the operation could not be queued
+    id: SyscallResult<u64>,
+    /// `None` once the `Op` completed
+    data: Option<T>,
+}
+
+impl<T: Completion> Op<T> {
+    fn new(sqe: IoUringSqe, data: T) -> Self {
+        Self {
+            id: URING.push(sqe),
+            data: Some(data),
+        }
+    }
+}
+
+impl<T: Completion> Future for Op<T> {
+    type Output = T::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let res = match self.id {
+            Ok(id) => match URING.poll_op(id, cx) {
+                Poll::Ready(res) => res,
+                Poll::Pending => return Poll::Pending,
+            },
+            Err(err) => Err(err),
+        };
+
+        let data = self.data.take().expect("`Op` polled after completion");
+
+        Poll::Ready(data.complete(res))
+    }
+}
+
+impl<T: Completion> Drop for Op<T> {
+    fn drop(&mut self) {
+        if let (Ok(id), Some(data)) = (self.id, self.data.take()) {
+            URING.abandon(id, Box::new(data));
+        }
+    }
+}
+
+/// An operation that does nothing
+pub fn nop() -> Op<()> {
+    Op::new(IoUringSqe::nop(), ())
+}
+
+/// Read into the spare capacity of `buf` at `offset` in `fd`, appending to its contents.
+/// An offset of `u64::MAX` reads from the current file position.
+///
+/// `fd` has to stay open until the operation completed.
+pub fn read_at(fd: Fd, mut buf: Vec<u8>, offset: u64) -> Op<ReadBuf> {
+    let spare = buf.capacity() - buf.len();
+    // Safety: `len` is in bounds of the allocation
+    let ptr = unsafe { buf.as_mut_ptr().add(buf.len()) };
+
+    let sqe = IoUringSqe::read(fd, ptr, spare as u32, offset);
+
+    Op::new(sqe, ReadBuf(buf))
+}
+
+/// Write `buf` at `offset` in `fd`.
+/// An offset of `u64::MAX` writes at the current file position.
+///
+/// `fd` has to stay open until the operation completed.
+pub fn write_at(fd: Fd, buf: Vec<u8>, offset: u64) -> Op<WriteBuf> {
+    let sqe = IoUringSqe::write(fd, buf.as_ptr(), buf.len() as u32, offset);
+
+    Op::new(sqe, WriteBuf(buf))
+}
+
+/// Flush the data and metadata of `fd` to disk
+pub fn fsync(fd: Fd) -> Op<()> {
+    Op::new(IoUringSqe::fsync(fd), ())
+}
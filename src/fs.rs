@@ -2,7 +2,7 @@ use core::ops::{Deref, DerefMut};
 
 use crate::{
     ffi::CStr,
-    io::{BufferedReader, BufferedWriter, Fd},
+    io::{AsyncBufferedReader, AsyncFd, BufferedReader, BufferedWriter, Fd},
     syscalls::{self, OpenFlags, OpenMode, SyscallResult},
 };
 
@@ -70,7 +70,63 @@ impl Drop for File {
     }
 }
 
+/// A `File` in non-blocking mode. Created by `File::into_async`
+pub struct AsyncFile {
+    // dropped before `file`, so that the fd is still open when it is deregistered
+    fd: AsyncFd,
+    file: File,
+}
+
+pub struct AsyncBufferedFile<const BUFFER_SIZE: usize> {
+    reader: AsyncBufferedReader<BUFFER_SIZE>,
+    file: File,
+}
+
+impl AsyncFile {
+    /// Get the underlying `File`
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn buffer<const BUFFER_SIZE: usize>(self) -> AsyncBufferedFile<BUFFER_SIZE> {
+        AsyncBufferedFile {
+            reader: self.fd.buffer(),
+            file: self.file,
+        }
+    }
+}
+
+impl Deref for AsyncFile {
+    type Target = AsyncFd;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fd
+    }
+}
+
+impl<const BUFFER_SIZE: usize> Deref for AsyncBufferedFile<BUFFER_SIZE> {
+    type Target = AsyncBufferedReader<BUFFER_SIZE>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.reader
+    }
+}
+
+impl<const BUFFER_SIZE: usize> DerefMut for AsyncBufferedFile<BUFFER_SIZE> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.reader
+    }
+}
+
 impl File {
+    /// Switch the file to non-blocking mode, to use it from async tasks
+    pub fn into_async(self) -> SyscallResult<AsyncFile> {
+        Ok(AsyncFile {
+            fd: self.fd.into_async()?,
+            file: self,
+        })
+    }
+
     pub fn open(path: impl AsRef<CStr>, flags: OpenFlags, mode: OpenMode) -> SyscallResult<Self> {
         syscalls::open(path, flags, mode).map(|fd| Self { fd })
     }
@@ -1,8 +1,18 @@
-use core::{fmt::Write, num::NonZeroUsize, str::Utf8Error};
+use core::{
+    fmt::Write,
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    str::Utf8Error,
+    task::{Context, Poll},
+};
 
 use crate::{
+    executor::reactor::{Interest, Registration},
     sync::{FutexMutexGuard, Mutex},
-    syscalls::SyscallError,
+    syscalls::{
+        self, helper::SyscallErrorKind, OpenFlags, PollEvents, PollFd, SyscallError, SyscallResult,
+    },
 };
 use alloc::string::String;
 use smallstr::SmallString;
@@ -12,6 +22,8 @@ pub enum Error {
     Syscall(SyscallError),
     UnexpectedEOF,
     InvalidUtf8(Utf8Error),
+    /// A line did not fit into a reader's buffer
+    LineTooLong,
 }
 
 impl From<SyscallError> for Error {
@@ -33,8 +45,11 @@ pub type IoResult<T> = core::result::Result<T, Error>;
 
 pub struct Fd(pub u32);
 impl Fd {
+    /// Blocks until the bytes can be written, even if the fd is in non-blocking mode.
+    /// This is the case while an `AsyncFd` for the same open file exists,
+    /// e.g. `async_stdout` for stdout and stdin of a terminal.
     pub fn write(&self, bytes: &[u8]) -> IoResult<usize> {
-        Ok(crate::syscalls::write(self.0, bytes)?)
+        self.retry(PollEvents::OUT, || crate::syscalls::write(self.0, bytes))
     }
 
     pub fn write_all(&self, bytes: &[u8]) -> IoResult<usize> {
@@ -54,8 +69,37 @@ impl Fd {
         }
     }
 
+    /// Blocks until there is data to read, even if the fd is in non-blocking mode
+    /// (see `write`).
     pub fn read(&self, dest: &mut [u8]) -> IoResult<NonZeroUsize> {
-        NonZeroUsize::new(crate::syscalls::read(self.0, dest)?).ok_or(Error::UnexpectedEOF)
+        let n_read = self.retry(PollEvents::IN, || crate::syscalls::read(self.0, dest))?;
+
+        NonZeroUsize::new(n_read).ok_or(Error::UnexpectedEOF)
+    }
+
+    /// Retry `f` until it does not fail with `EAGAIN`, waiting for `events` in between
+    fn retry(
+        &self,
+        events: PollEvents,
+        mut f: impl FnMut() -> SyscallResult<usize>,
+    ) -> IoResult<usize> {
+        loop {
+            match f() {
+                Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {
+                    let mut fds = [PollFd {
+                        fd: self.0,
+                        events,
+                        revents: PollEvents::empty(),
+                    }];
+
+                    match syscalls::poll(&mut fds, -1) {
+                        Err(err) if err.kind() != SyscallErrorKind::EINTR => return Err(err.into()),
+                        _ => {}
+                    }
+                }
+                res => return Ok(res?),
+            }
+        }
     }
 
     pub fn read_exact(&self, dest: &mut [u8]) -> IoResult<usize> {
@@ -74,6 +118,11 @@ impl Fd {
             }
         }
     }
+
+    /// Switch the fd to non-blocking mode, to use it from async tasks
+    pub fn into_async(self) -> SyscallResult<AsyncFd> {
+        AsyncFd::new(self)
+    }
 }
 
 impl Write for Fd {
@@ -404,6 +453,316 @@ impl<const BUFFER_SIZE: usize> Write for BufferedWriter<BUFFER_SIZE> {
     }
 }
 
+/// An `Fd` in non-blocking mode, whose operations wait for the fd
+/// to become ready without blocking the executor.
+///
+/// Like `Fd`, it does not close the fd when dropped.
+pub struct AsyncFd {
+    fd: Fd,
+    /// `None` if epoll does not support the fd. This is the case for regular
+    /// files, which are always ready.
+    registration: Option<Registration>,
+    /// Whether `NONBLOCK` was set by us and has to be cleared on drop
+    clear_nonblock: bool,
+}
+
+impl AsyncFd {
+    /// Set `OpenFlags::NONBLOCK` on `fd` and register it with the reactor.
+    ///
+    /// The flag belongs to the open file, so it also affects other fds
+    /// referring to it, e.g. stdin and stdout of a terminal. It is cleared
+    /// again when the `AsyncFd` is dropped.
+    pub fn new(fd: Fd) -> SyscallResult<Self> {
+        let flags = syscalls::get_status_flags(fd)?;
+
+        let clear_nonblock = !flags.contains(OpenFlags::NONBLOCK);
+        if clear_nonblock {
+            syscalls::set_status_flags(fd, flags | OpenFlags::NONBLOCK)?;
+        }
+
+        let registration = match Registration::new(fd) {
+            Ok(registration) => Some(registration),
+            Err(err) if err.kind() == SyscallErrorKind::EPERM => None,
+            Err(err) => {
+                if clear_nonblock {
+                    syscalls::set_status_flags(fd, flags)?;
+                }
+
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            fd,
+            registration,
+            clear_nonblock,
+        })
+    }
+
+    pub fn fd(&self) -> Fd {
+        self.fd
+    }
+
+    fn poll_io<T>(
+        &self,
+        interest: Interest,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut() -> SyscallResult<T>,
+    ) -> Poll<SyscallResult<T>> {
+        match &self.registration {
+            Some(registration) => registration.poll_io(interest, cx, f),
+            None => Poll::Ready(f()),
+        }
+    }
+
+    pub fn poll_read(&self, cx: &mut Context<'_>, dest: &mut [u8]) -> Poll<IoResult<NonZeroUsize>> {
+        self.poll_io(Interest::READABLE, cx, || syscalls::read(self.fd.0, dest))
+            .map(|res| NonZeroUsize::new(res?).ok_or(Error::UnexpectedEOF))
+    }
+
+    pub fn poll_write(&self, cx: &mut Context<'_>, bytes: &[u8]) -> Poll<IoResult<usize>> {
+        self.poll_io(Interest::WRITABLE, cx, || syscalls::write(self.fd.0, bytes))
+            .map(|res| Ok(res?))
+    }
+
+    pub fn read<'a>(&'a self, dest: &'a mut [u8]) -> ReadFuture<'a> {
+        ReadFuture { fd: self, dest }
+    }
+
+    pub fn write<'a>(&'a self, bytes: &'a [u8]) -> WriteFuture<'a> {
+        WriteFuture { fd: self, bytes }
+    }
+
+    pub async fn write_all(&self, bytes: &[u8]) -> IoResult<usize> {
+        let mut n_written_total = 0;
+        loop {
+            let n_written = self.write(&bytes[n_written_total..]).await?;
+
+            if n_written == 0 {
+                return Ok(n_written_total);
+            }
+
+            n_written_total += n_written;
+
+            if n_written_total == bytes.len() {
+                return Ok(n_written_total);
+            }
+        }
+    }
+
+    pub async fn read_exact(&self, dest: &mut [u8]) -> IoResult<usize> {
+        let mut n_read_total = 0;
+        loop {
+            let n_read = self.read(&mut dest[n_read_total..]).await;
+
+            if let Err(Error::UnexpectedEOF) = n_read {
+                return Ok(n_read_total);
+            }
+
+            n_read_total += n_read?.get();
+
+            if n_read_total == dest.len() {
+                return Ok(n_read_total);
+            }
+        }
+    }
+
+    pub fn buffer<const BUFFER_SIZE: usize>(self) -> AsyncBufferedReader<BUFFER_SIZE> {
+        AsyncBufferedReader::new(self)
+    }
+}
+
+impl Drop for AsyncFd {
+    fn drop(&mut self) {
+        // deregister before touching the flags, in case the fd was already closed
+        drop(self.registration.take());
+
+        if self.clear_nonblock {
+            let res = syscalls::get_status_flags(self.fd)
+                .and_then(|flags| syscalls::set_status_flags(self.fd, flags - OpenFlags::NONBLOCK));
+
+            res.unwrap_or_else(|err| panic!("Failed to reset flags of fd {}: {}", self.fd.0, err));
+        }
+    }
+}
+
+/// Future returned by `AsyncFd::read`
+pub struct ReadFuture<'a> {
+    fd: &'a AsyncFd,
+    dest: &'a mut [u8],
+}
+
+impl Future for ReadFuture<'_> {
+    type Output = IoResult<NonZeroUsize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.fd.poll_read(cx, this.dest)
+    }
+}
+
+/// Future returned by `AsyncFd::write`
+pub struct WriteFuture<'a> {
+    fd: &'a AsyncFd,
+    bytes: &'a [u8],
+}
+
+impl Future for WriteFuture<'_> {
+    type Output = IoResult<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.fd.poll_write(cx, self.bytes)
+    }
+}
+
+/// The async counterpart of `BufferedReader`
+pub struct AsyncBufferedReader<const BUFFER_SIZE: usize> {
+    fd: AsyncFd,
+    buffer: [u8; BUFFER_SIZE],
+    cursor: usize,
+}
+
+impl<const BUFFER_SIZE: usize> AsyncBufferedReader<BUFFER_SIZE> {
+    pub const fn new(fd: AsyncFd) -> Self {
+        Self {
+            fd,
+            buffer: [0; BUFFER_SIZE],
+            cursor: 0,
+        }
+    }
+
+    /// Fails with `LineTooLong` if the buffer is full without containing a whole line.
+    /// The buffered start of the line is dropped, so the next line read returns its rest.
+    async fn fill_buffer(&mut self) -> IoResult<()> {
+        if self.cursor >= BUFFER_SIZE - 1 {
+            self.cursor = 0;
+
+            return Err(Error::LineTooLong);
+        }
+
+        let n_read = self.fd.read(&mut self.buffer[self.cursor..]).await?;
+
+        self.cursor += n_read.get();
+
+        Ok(())
+    }
+
+    /// The length of the first line in the buffer, including the '\n'
+    fn line_len(&self) -> Option<usize> {
+        // TODO: checking the byte for \n might cause utf-8 problems
+        self.buffer[..self.cursor]
+            .iter()
+            .position(|&x| x == b'\n')
+            .map(|i| i + 1)
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.buffer.copy_within(n..self.cursor, 0);
+
+        self.cursor -= n;
+    }
+
+    pub async fn read_line(&mut self, line: &mut String) -> IoResult<()> {
+        loop {
+            if let Some(len) = self.line_len() {
+                line.push_str(core::str::from_utf8(&self.buffer[..len])?);
+
+                self.consume(len);
+
+                break Ok(());
+            }
+
+            self.fill_buffer().await?;
+        }
+    }
+
+    pub async fn read_line_inline<const N_INLINE: usize>(
+        &mut self,
+        line: &mut SmallString<[u8; N_INLINE]>,
+    ) -> IoResult<()> {
+        loop {
+            if let Some(len) = self.line_len() {
+                line.push_str(core::str::from_utf8(&self.buffer[..len])?);
+
+                self.consume(len);
+
+                break Ok(());
+            }
+
+            self.fill_buffer().await?;
+        }
+    }
+
+    pub fn lines(&mut self) -> AsyncLines<'_, BUFFER_SIZE> {
+        AsyncLines { reader: self }
+    }
+
+    pub fn inline_lines<const LINE_SIZE: usize>(
+        &mut self,
+    ) -> AsyncInlineLines<'_, BUFFER_SIZE, LINE_SIZE> {
+        AsyncInlineLines { reader: self }
+    }
+
+    /// Get the underlying `AsyncFd`
+    pub fn get_ref(&self) -> &AsyncFd {
+        &self.fd
+    }
+}
+
+/// The async counterpart of `Lines`. Call `next` in a loop:
+/// `while let Some(line) = lines.next().await {}`
+pub struct AsyncLines<'reader, const BUFFER_SIZE: usize> {
+    reader: &'reader mut AsyncBufferedReader<BUFFER_SIZE>,
+}
+
+impl<const BUFFER_SIZE: usize> AsyncLines<'_, BUFFER_SIZE> {
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Option<IoResult<String>> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line).await {
+            Err(Error::UnexpectedEOF) => None,
+            res => Some(res.map(|_| line)),
+        }
+    }
+}
+
+/// The async counterpart of `InlineLines`
+pub struct AsyncInlineLines<'reader, const BUFFER_SIZE: usize, const LINE_SIZE: usize> {
+    reader: &'reader mut AsyncBufferedReader<BUFFER_SIZE>,
+}
+
+impl<const BUFFER_SIZE: usize, const LINE_SIZE: usize>
+    AsyncInlineLines<'_, BUFFER_SIZE, LINE_SIZE>
+{
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> Option<IoResult<SmallString<[u8; LINE_SIZE]>>> {
+        let mut line = SmallString::new();
+        match self.reader.read_line_inline(&mut line).await {
+            Err(Error::UnexpectedEOF) => None,
+            res => Some(res.map(|_| line)),
+        }
+    }
+}
+
+/// Read stdin from async tasks.
+///
+/// Input already buffered by `stdin()` is not seen by the returned reader.
+/// Stdin is in non-blocking mode until the reader is dropped. For a terminal this
+/// also affects stdout, and other processes using the same terminal.
+pub fn async_stdin() -> SyscallResult<AsyncBufferedReader<STD_IN_BUFFER_SIZE>> {
+    Ok(StdIn::FD.into_async()?.buffer())
+}
+
+/// Write to stdout from async tasks.
+///
+/// Flushes `stdout()` first, so that its buffered output is not reordered.
+/// Like `async_stdin`, this puts stdout into non-blocking mode while the `AsyncFd` lives.
+pub fn async_stdout() -> IoResult<AsyncFd> {
+    stdout().flush()?;
+
+    Ok(StdOut::FD.into_async()?)
+}
+
 pub fn cleanup() {
     stdout().flush().expect("Failed to flush stdout");
     stderr().flush().expect("Failed to flush stderr");
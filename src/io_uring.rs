@@ -0,0 +1,386 @@
+//! A safe wrapper around the submission and completion queues of io_uring.
+//!
+//! Entries are pushed to the submission queue, handed to the kernel with
+//! `IoUring::submit` and their results are read from the completion queue.
+//! `executor::uring` uses this to drive async operations.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{
+    io::Fd,
+    syscalls::{
+        self, io_uring_enter, io_uring_register, io_uring_setup, mmap, munmap, IoUringCqe,
+        IoUringEnterFlags, IoUringFeatures, IoUringOp, IoUringParams, IoUringRegisterOp,
+        IoUringSqe, IoUringSqeFlags, MMapFlags, MProt, SyscallResult, IORING_OFF_CQ_RING,
+        IORING_OFF_SQES, IORING_OFF_SQ_RING,
+    },
+};
+
+/// Returned by `IoUring::push` if there is no space in the submission queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl core::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "submission queue is full")
+    }
+}
+
+/// A memory mapping shared with the kernel
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: u32, len: usize, offset: u64) -> SyscallResult<Self> {
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                MProt::READ | MProt::WRITE,
+                MMapFlags::SHARED | MMapFlags::POPULATE,
+                fd as i32,
+                offset,
+            )?
+        };
+
+        Ok(Self { ptr, len })
+    }
+
+    /// # Safety
+    /// `offset` must be in bounds and aligned for `T`
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.add(offset as usize) as *mut T
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { munmap(self.ptr, self.len) }.expect("Failed to unmap io_uring");
+    }
+}
+
+struct SubmissionQueue {
+    head: *const AtomicU32,
+    tail: *const AtomicU32,
+    ring_mask: u32,
+    ring_entries: u32,
+    sqes: *mut IoUringSqe,
+    /// Entries up to here have been pushed, but not yet made visible to the kernel
+    local_tail: u32,
+}
+
+struct CompletionQueue {
+    head: *const AtomicU32,
+    tail: *const AtomicU32,
+    ring_mask: u32,
+    cqes: *const IoUringCqe,
+}
+
+/// An io_uring instance
+pub struct IoUring {
+    fd: Fd,
+    features: IoUringFeatures,
+    sq: SubmissionQueue,
+    cq: CompletionQueue,
+    _sq_ring: Mapping,
+    /// `None` if the completion queue is part of `sq_ring`
+    _cq_ring: Option<Mapping>,
+    _sqes: Mapping,
+}
+
+// Safety: the rings are only accessed through `&mut self`
+unsafe impl Send for IoUring {}
+unsafe impl Sync for IoUring {}
+
+impl IoUring {
+    /// Set up an io_uring with space for (at least) `entries` submissions
+    pub fn new(entries: u32) -> SyscallResult<Self> {
+        let mut params = IoUringParams::default();
+
+        let fd = unsafe { io_uring_setup(entries, &mut params as *mut _) }.map(Fd)?;
+
+        Self::map(fd, &params).map_err(|err| {
+            syscalls::close(fd).expect("Failed to close io_uring");
+            err
+        })
+    }
+
+    fn map(fd: Fd, params: &IoUringParams) -> SyscallResult<Self> {
+        let features = IoUringFeatures::from_bits_truncate(params.features);
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize
+            + params.cq_entries as usize * core::mem::size_of::<IoUringCqe>();
+
+        let (sq_ring, cq_ring) = if features.contains(IoUringFeatures::SINGLE_MMAP) {
+            let ring = Mapping::new(fd.0, sq_len.max(cq_len), IORING_OFF_SQ_RING)?;
+
+            (ring, None)
+        } else {
+            let sq_ring = Mapping::new(fd.0, sq_len, IORING_OFF_SQ_RING)?;
+            let cq_ring = Mapping::new(fd.0, cq_len, IORING_OFF_CQ_RING)?;
+
+            (sq_ring, Some(cq_ring))
+        };
+
+        let sqes = Mapping::new(
+            fd.0,
+            params.sq_entries as usize * core::mem::size_of::<IoUringSqe>(),
+            IORING_OFF_SQES,
+        )?;
+
+        // Safety: the offsets were provided by the kernel
+        unsafe {
+            let sq_off = &params.sq_off;
+            let cq_off = &params.cq_off;
+
+            // Submission queue entries are always used in order,
+            // so the indirection array maps every slot to itself
+            let array = sq_ring.at::<u32>(sq_off.array);
+            for i in 0..params.sq_entries {
+                *array.add(i as usize) = i;
+            }
+
+            let sq = SubmissionQueue {
+                head: sq_ring.at(sq_off.head),
+                tail: sq_ring.at(sq_off.tail),
+                ring_mask: *sq_ring.at(sq_off.ring_mask),
+                ring_entries: *sq_ring.at(sq_off.ring_entries),
+                sqes: sqes.ptr as *mut IoUringSqe,
+                local_tail: (*sq_ring.at::<AtomicU32>(sq_off.tail)).load(Ordering::Relaxed),
+            };
+
+            let cq_map = cq_ring.as_ref().unwrap_or(&sq_ring);
+
+            let cq = CompletionQueue {
+                head: cq_map.at(cq_off.head),
+                tail: cq_map.at(cq_off.tail),
+                ring_mask: *cq_map.at(cq_off.ring_mask),
+                cqes: cq_map.at(cq_off.cqes),
+            };
+
+            Ok(Self {
+                fd,
+                features,
+                sq,
+                cq,
+                _sq_ring: sq_ring,
+                _cq_ring: cq_ring,
+                _sqes: sqes,
+            })
+        }
+    }
+
+    /// Get the io_uring's fd. It is readable while there are completions.
+    pub fn fd(&self) -> Fd {
+        self.fd
+    }
+
+    pub fn features(&self) -> IoUringFeatures {
+        self.features
+    }
+
+    /// The number of entries that fit into the submission queue
+    pub fn capacity(&self) -> usize {
+        self.sq.ring_entries as usize
+    }
+
+    /// The number of entries that have not been consumed by the kernel yet
+    pub fn pending(&self) -> usize {
+        let head = unsafe { (*self.sq.head).load(Ordering::Acquire) };
+
+        self.sq.local_tail.wrapping_sub(head) as usize
+    }
+
+    /// The number of entries that can be pushed before the queue is full
+    pub fn space_left(&self) -> usize {
+        self.capacity() - self.pending()
+    }
+
+    /// Add an entry to the submission queue. It is passed to the kernel by the next `submit`.
+    ///
+    /// # Safety
+    /// The buffers and fds referenced by `sqe` have to stay valid until its completion
+    /// was received.
+    pub unsafe fn push(&mut self, sqe: &IoUringSqe) -> Result<(), QueueFull> {
+        if self.space_left() == 0 {
+            return Err(QueueFull);
+        }
+
+        let index = self.sq.local_tail & self.sq.ring_mask;
+        self.sq.sqes.add(index as usize).write(*sqe);
+
+        self.sq.local_tail = self.sq.local_tail.wrapping_add(1);
+
+        Ok(())
+    }
+
+    /// Pass the pushed entries to the kernel. Returns the number of submitted entries.
+    pub fn submit(&mut self) -> SyscallResult<usize> {
+        self.submit_and_wait(0)
+    }
+
+    /// Like `submit`, but also wait until at least `want` completions are available
+    pub fn submit_and_wait(&mut self, want: u32) -> SyscallResult<usize> {
+        // publish the entries before the new tail
+        unsafe { (*self.sq.tail).store(self.sq.local_tail, Ordering::Release) };
+
+        // Without `SQPOLL` the kernel only consumes entries in `io_uring_enter`
+        let to_submit = self.pending() as u32;
+
+        if to_submit == 0 && want == 0 {
+            return Ok(0);
+        }
+
+        let flags = if want > 0 {
+            IoUringEnterFlags::GETEVENTS
+        } else {
+            IoUringEnterFlags::empty()
+        };
+
+        unsafe { io_uring_enter(self.fd.0, to_submit, want, flags) }.map(|n| n as usize)
+    }
+
+    /// Take the oldest entry from the completion queue
+    pub fn pop(&mut self) -> Option<IoUringCqe> {
+        unsafe {
+            let head = (*self.cq.head).load(Ordering::Relaxed);
+            let tail = (*self.cq.tail).load(Ordering::Acquire);
+
+            if head == tail {
+                return None;
+            }
+
+            let cqe = *self.cq.cqes.add((head & self.cq.ring_mask) as usize);
+
+            // hand the slot back to the kernel after we copied the entry
+            (*self.cq.head).store(head.wrapping_add(1), Ordering::Release);
+
+            Some(cqe)
+        }
+    }
+
+    /// Register `fds`, so that entries can refer to them by their index
+    /// using `IoUringSqeFlags::FIXED_FILE`
+    pub fn register_files(&self, fds: &[Fd]) -> SyscallResult<()> {
+        unsafe {
+            io_uring_register(
+                self.fd.0,
+                IoUringRegisterOp::RegisterFiles,
+                fds.as_ptr() as *const (),
+                fds.len() as u32,
+            )
+            .map(|_| ())
+        }
+    }
+
+    pub fn unregister_files(&self) -> SyscallResult<()> {
+        unsafe {
+            io_uring_register(
+                self.fd.0,
+                IoUringRegisterOp::UnregisterFiles,
+                ptr::null(),
+                0,
+            )
+            .map(|_| ())
+        }
+    }
+
+    /// Signal `event_fd` whenever a completion is posted
+    pub fn register_event_fd(&self, event_fd: Fd) -> SyscallResult<()> {
+        unsafe {
+            io_uring_register(
+                self.fd.0,
+                IoUringRegisterOp::RegisterEventFd,
+                &event_fd.0 as *const u32 as *const (),
+                1,
+            )
+            .map(|_| ())
+        }
+    }
+}
+
+impl Drop for IoUring {
+    fn drop(&mut self) {
+        // NOTE: the mappings are dropped after this, but the kernel keeps
+        // the ring alive until they are unmapped
+        syscalls::close(self.fd).expect("Failed to close io_uring");
+    }
+}
+
+impl IoUringSqe {
+    /// An entry for `op` with all other fields zeroed
+    pub const fn new(op: IoUringOp) -> Self {
+        Self {
+            opcode: op,
+            flags: IoUringSqeFlags::empty(),
+            ioprio: 0,
+            fd: -1,
+            off: 0,
+            addr: 0,
+            len: 0,
+            op_flags: 0,
+            user_data: 0,
+            buf_index: 0,
+            personality: 0,
+            splice_fd_in: 0,
+            pad: [0; 2],
+        }
+    }
+
+    /// Does nothing. Useful to test the ring.
+    pub const fn nop() -> Self {
+        Self::new(IoUringOp::Nop)
+    }
+
+    /// Read up to `len` bytes at `offset` into `buf`.
+    /// An offset of `u64::MAX` reads from the current file position.
+    pub fn read(fd: Fd, buf: *mut u8, len: u32, offset: u64) -> Self {
+        Self {
+            fd: fd.0 as i32,
+            addr: buf as u64,
+            len,
+            off: offset,
+            ..Self::new(IoUringOp::Read)
+        }
+    }
+
+    /// Write `len` bytes from `buf` at `offset`.
+    /// An offset of `u64::MAX` writes at the current file position.
+    pub fn write(fd: Fd, buf: *const u8, len: u32, offset: u64) -> Self {
+        Self {
+            fd: fd.0 as i32,
+            addr: buf as u64,
+            len,
+            off: offset,
+            ..Self::new(IoUringOp::Write)
+        }
+    }
+
+    pub fn fsync(fd: Fd) -> Self {
+        Self {
+            fd: fd.0 as i32,
+            ..Self::new(IoUringOp::Fsync)
+        }
+    }
+
+    pub fn close(fd: Fd) -> Self {
+        Self {
+            fd: fd.0 as i32,
+            ..Self::new(IoUringOp::Close)
+        }
+    }
+
+    pub const fn user_data(self, user_data: u64) -> Self {
+        Self { user_data, ..self }
+    }
+
+    pub const fn flags(self, flags: IoUringSqeFlags) -> Self {
+        Self { flags, ..self }
+    }
+}
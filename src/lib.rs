@@ -2,6 +2,7 @@
 #![feature(decl_macro)]
 #![feature(asm, asm_sym, asm_const)]
 #![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 #![feature(naked_functions)]
 #![feature(lang_items)]
 #![feature(core_intrinsics)]
@@ -20,11 +21,13 @@ pub mod start;
 pub use start::create_init;
 
 pub mod allocator;
+pub mod backtrace;
 pub mod env;
 pub mod executor;
 pub mod ffi;
 pub mod fs;
 pub mod io;
+pub mod io_uring;
 pub mod lang_items;
 pub mod logger;
 pub mod stack_protection;
@@ -2,6 +2,7 @@
 #![no_main]
 #![feature(asm, asm_sym, asm_const)]
 #![feature(naked_functions)]
+#![feature(allocator_api)]
 #![allow(clippy::missing_safety_doc)]
 
 use barebones_x86_linux::*;
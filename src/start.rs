@@ -1,5 +1,11 @@
+use crate::allocator::{DebugAlloc, HeapBackend, HugePages};
+
 pub struct RuntimeOptions {
     pub(crate) alloc: bool,
+    pub(crate) heap_backend: HeapBackend,
+    pub(crate) debug_alloc: Option<DebugAlloc>,
+    pub(crate) leak_tracking: bool,
+    pub(crate) huge_pages: HugePages,
     pub(crate) logging: bool,
     pub(crate) segv_handling: bool,
     pub(crate) stack_protection: bool,
@@ -11,6 +17,10 @@ impl RuntimeOptions {
     pub const fn all() -> Self {
         Self {
             alloc: true,
+            heap_backend: HeapBackend::Brk,
+            debug_alloc: None,
+            leak_tracking: false,
+            huge_pages: HugePages::Off,
             logging: true,
             segv_handling: true,
             stack_protection: true,
@@ -26,6 +36,10 @@ impl RuntimeOptions {
     pub const unsafe fn none() -> Self {
         Self {
             alloc: false,
+            heap_backend: HeapBackend::Brk,
+            debug_alloc: None,
+            leak_tracking: false,
+            huge_pages: HugePages::Off,
             logging: false,
             segv_handling: false,
             stack_protection: false,
@@ -87,6 +101,26 @@ impl RuntimeOptions {
     pub const fn add_alloc(self) -> Self {
         unsafe { self.add_only_alloc() }
     }
+    /// Select where the allocator gets its memory from. Defaults to `HeapBackend::Brk`.
+    pub const fn with_heap_backend(mut self, backend: HeapBackend) -> Self {
+        self.heap_backend = backend;
+        self.add_alloc()
+    }
+    /// Check for use-after-free, double free and mismatched layouts in the allocator
+    pub const fn with_debug_alloc(mut self, debug: DebugAlloc) -> Self {
+        self.debug_alloc = Some(debug);
+        self.add_alloc()
+    }
+    /// Record where each allocation was made, and print the ones that were never freed at exit
+    pub const fn with_leak_tracking(mut self) -> Self {
+        self.leak_tracking = true;
+        self.add_alloc()
+    }
+    /// Back large allocations with huge pages. Defaults to `HugePages::Off`.
+    pub const fn with_huge_pages(mut self, huge_pages: HugePages) -> Self {
+        self.huge_pages = huge_pages;
+        self.add_alloc()
+    }
     pub const fn add_logging(self) -> Self {
         unsafe { self.add_only_logging().add_io() }
     }
@@ -167,7 +201,12 @@ pub macro create_init {
 
 
             if RUNTIME_OPTIONS.alloc {
-                $crate::allocator::init().expect("Failed to initialize global allocator");
+                $crate::allocator::init(
+                    RUNTIME_OPTIONS.heap_backend,
+                    RUNTIME_OPTIONS.debug_alloc,
+                    RUNTIME_OPTIONS.leak_tracking,
+                    RUNTIME_OPTIONS.huge_pages,
+                ).expect("Failed to initialize global allocator");
             }
 
             if RUNTIME_OPTIONS.logging {
@@ -188,11 +227,10 @@ pub macro create_init {
             }
 
             if RUNTIME_OPTIONS.tls {
-                $crate::tls::setup_tls($crate::tls::Tls {
-                    stack_base,
-                    stack_limit,
-                })
-                .expect("Failed to set tls");
+                $crate::tls::init(&env);
+
+                $crate::tls::setup_tls($crate::tls::Tls::new(stack_base, stack_limit))
+                    .expect("Failed to set tls");
             }
 
             if RUNTIME_OPTIONS.segv_handling {
@@ -225,6 +263,8 @@ pub macro create_init {
             }
 
             if RUNTIME_OPTIONS.alloc {
+                $crate::thread::free_orphaned_futexes();
+
                 $crate::allocator::deinit().expect("Failed to de-initialize global allocator");
             }
 
@@ -0,0 +1,48 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::wait_on;
+use crate::syscalls::futex_wake;
+
+/// Blocks threads until `n` of them are waiting, then releases them all at once.
+/// Can be reused, e.g. to separate the phases of a parallel job.
+pub struct Barrier {
+    n: u32,
+    /// The number of threads waiting in the current generation
+    arrived: AtomicU32,
+    /// Incremented whenever all threads arrived
+    generation: AtomicU32,
+}
+
+impl Barrier {
+    pub const fn new(n: u32) -> Self {
+        assert!(n > 0, "a barrier needs at least one thread");
+
+        Barrier {
+            n,
+            arrived: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    /// Wait until `n` threads are waiting.
+    /// Returns true for exactly one of them, e.g. to merge the results of a phase.
+    pub fn wait(&self) -> bool {
+        let generation = self.generation.load(Ordering::Acquire);
+
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 == self.n {
+            // Nobody of the next generation arrives before they see the new generation
+            self.arrived.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+
+            unsafe { futex_wake(&self.generation, None) }.expect("Failed to wake futex");
+
+            return true;
+        }
+
+        while self.generation.load(Ordering::Acquire) == generation {
+            wait_on(&self.generation, generation);
+        }
+
+        false
+    }
+}
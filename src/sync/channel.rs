@@ -0,0 +1,335 @@
+//! Channels for passing values between threads, which block the calling thread.
+//! Both sides can be cloned, and each value is received by exactly one receiver.
+//!
+//! Use `executor::channel` to pass values between tasks instead.
+
+use core::{
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    time::Duration,
+};
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use super::{Condvar, FutexMutexGuard, Mutex};
+use crate::syscalls::{self, ClockId};
+
+pub use crate::executor::channel::{RecvError, SendError, TryRecvError, TrySendError};
+
+/// Returned by `recv_timeout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// No value arrived in time
+    Timeout,
+    /// All senders were dropped and there are no values left
+    Closed,
+}
+
+impl core::fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => write!(f, "timed out waiting on channel"),
+            RecvTimeoutError::Closed => write!(f, "channel is closed"),
+        }
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Notified when a value is sent or the last sender is dropped
+    not_empty: Condvar,
+    /// Notified when a value is received or the last receiver is dropped
+    not_full: Condvar,
+}
+
+struct State<T> {
+    queue: Queue<T>,
+    /// `None` for unbounded channels
+    capacity: Option<usize>,
+    senders: usize,
+    receivers: usize,
+    /// The number of threads waiting in `recv`, so that `send` can skip notifying
+    waiting_receivers: usize,
+    /// The number of threads waiting in `send`
+    waiting_senders: usize,
+}
+
+/// The values in a channel. They are only moved in and out while the `Mutex` in `Shared`
+/// is locked, and never shared between threads, so `T` does not need to be `Sync`.
+struct Queue<T>(VecDeque<T>);
+
+// Safety: `Queue` is private, and the channel never hands out references into it
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Deref for Queue<T> {
+    type Target = VecDeque<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Queue<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> State<T> {
+    fn is_full(&self) -> bool {
+        self.capacity
+            .map(|capacity| self.queue.len() >= capacity)
+            .unwrap_or(false)
+    }
+}
+
+fn shared<T: Send>(capacity: Option<usize>) -> Pin<Arc<Shared<T>>> {
+    // Safety: The Mutex is `Pin`ned by the `Arc::pin`.
+    unsafe {
+        Arc::pin(Shared {
+            state: Mutex::new(State {
+                queue: Queue(VecDeque::new()),
+                capacity,
+                senders: 1,
+                receivers: 1,
+                waiting_receivers: 0,
+                waiting_senders: 0,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        })
+    }
+}
+
+/// Create a channel without a capacity limit. Sending never blocks.
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let shared = shared(None);
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// Create a channel that holds up to `capacity` values.
+/// Sending blocks while the channel is full.
+pub fn sync_channel<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "sync_channel capacity must be non-zero");
+
+    let shared = shared(Some(capacity));
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// The sending side of a channel
+pub struct Sender<T: Send> {
+    shared: Pin<Arc<Shared<T>>>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Send `value`, waiting while the channel is full.
+    /// Fails if all receivers were dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.state.lock();
+
+        loop {
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+
+            if !state.is_full() {
+                break;
+            }
+
+            state.waiting_senders += 1;
+            state = self.shared.not_full.wait(state);
+            state.waiting_senders -= 1;
+        }
+
+        self.push(state, value);
+
+        Ok(())
+    }
+
+    /// Send `value` if there is space
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let state = self.shared.state.lock();
+
+        if state.receivers == 0 {
+            return Err(TrySendError::Closed(value));
+        }
+
+        if state.is_full() {
+            return Err(TrySendError::Full(value));
+        }
+
+        self.push(state, value);
+
+        Ok(())
+    }
+
+    fn push(&self, mut state: FutexMutexGuard<'_, State<T>>, value: T) {
+        state.queue.push_back(value);
+
+        let notify = state.waiting_receivers > 0;
+
+        drop(state);
+
+        if notify {
+            self.shared.not_empty.notify_one();
+        }
+    }
+
+    /// Returns true if all receivers were dropped
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().receivers == 0
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+
+        state.senders -= 1;
+
+        let last = state.senders == 0;
+
+        drop(state);
+
+        if last {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+/// The receiving side of a channel
+pub struct Receiver<T: Send> {
+    shared: Pin<Arc<Shared<T>>>,
+}
+
+impl<T: Send> Receiver<T> {
+    /// Wait for a value. Fails once all senders were dropped and there are no values left.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like `recv`, but gives up after `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // A deadline that can't be represented is never reached
+        self.recv_until(now().checked_add(timeout))
+    }
+
+    /// Take a value if there is one
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.state.lock();
+
+        match state.queue.pop_front() {
+            Some(value) => {
+                self.popped(state);
+
+                Ok(value)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Receive values until all senders were dropped
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        core::iter::from_fn(move || self.recv().ok())
+    }
+
+    fn recv_until(&self, deadline: Option<Duration>) -> Result<T, RecvTimeoutError> {
+        let mut state = self.shared.state.lock();
+
+        loop {
+            if let Some(value) = state.queue.pop_front() {
+                self.popped(state);
+
+                return Ok(value);
+            }
+
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Closed);
+            }
+
+            state.waiting_receivers += 1;
+
+            state = match deadline {
+                Some(deadline) => {
+                    let timeout = match deadline.checked_sub(now()) {
+                        Some(timeout) => timeout,
+                        None => {
+                            state.waiting_receivers -= 1;
+
+                            return Err(RecvTimeoutError::Timeout);
+                        }
+                    };
+
+                    self.shared.not_empty.wait_timeout(state, timeout).0
+                }
+                None => self.shared.not_empty.wait(state),
+            };
+
+            state.waiting_receivers -= 1;
+        }
+    }
+
+    /// Let a sender know that there is space now
+    fn popped(&self, state: FutexMutexGuard<'_, State<T>>) {
+        let notify = state.waiting_senders > 0;
+
+        drop(state);
+
+        if notify {
+            self.shared.not_full.notify_one();
+        }
+    }
+}
+
+impl<T: Send> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().receivers += 1;
+
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+
+        state.receivers -= 1;
+
+        let last = state.receivers == 0;
+
+        drop(state);
+
+        if last {
+            self.shared.not_full.notify_all();
+        }
+    }
+}
+
+fn now() -> Duration {
+    syscalls::clock_gettime(ClockId::Monotonic).expect("Failed to read the monotonic clock")
+}
@@ -0,0 +1,107 @@
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use super::{wait_on, wait_on_timeout, FutexMutexGuard, Mutex};
+use crate::syscalls::futex_wake;
+
+/// A condition variable for `FutexMutex`.
+/// Waiting unlocks the mutex, blocks until the condvar is notified and locks the mutex again.
+/// Waits can return without a notification, so the condition has to be checked in a loop,
+/// e.g. with `wait_while`.
+pub struct Condvar {
+    /// Incremented by every notification, so that a waiter notices
+    /// notifications between unlocking the mutex and going to sleep
+    futex: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            futex: AtomicU32::new(0),
+        }
+    }
+
+    /// Unlock `guard`'s mutex until we are notified
+    pub fn wait<'d, T>(&self, guard: FutexMutexGuard<'d, T>) -> FutexMutexGuard<'d, T> {
+        self.wait_inner(guard, None).0
+    }
+
+    /// Wait until `condition` returns false. It is called with the mutex locked.
+    pub fn wait_while<'d, T>(
+        &self,
+        mut guard: FutexMutexGuard<'d, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> FutexMutexGuard<'d, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    /// Like `wait`, but gives up after `timeout`.
+    /// The returned bool is true if the wait timed out.
+    pub fn wait_timeout<'d, T>(
+        &self,
+        guard: FutexMutexGuard<'d, T>,
+        timeout: Duration,
+    ) -> (FutexMutexGuard<'d, T>, bool) {
+        self.wait_inner(guard, Some(timeout))
+    }
+
+    fn wait_inner<'d, T>(
+        &self,
+        guard: FutexMutexGuard<'d, T>,
+        timeout: Option<Duration>,
+    ) -> (FutexMutexGuard<'d, T>, bool) {
+        // Notifications after this are seen, since the futex will have changed
+        let seq = self.futex.load(Ordering::Relaxed);
+
+        let mutex_var = guard.mutex_var;
+        let data = guard.data;
+
+        drop(guard);
+
+        let timed_out = match timeout {
+            Some(timeout) => wait_on_timeout(&self.futex, seq, timeout),
+            None => {
+                wait_on(&self.futex, seq);
+                false
+            }
+        };
+
+        // Safety: `mutex_var` belongs to a mutex that is borrowed for `'d`
+        Mutex::<T>::lock_var(unsafe { &*mutex_var });
+
+        let guard = FutexMutexGuard {
+            mutex_var,
+            data,
+            _phantom: PhantomData,
+        };
+
+        (guard, timed_out)
+    }
+
+    /// Wake one waiting thread
+    pub fn notify_one(&self) {
+        self.futex.fetch_add(1, Ordering::Relaxed);
+
+        unsafe { futex_wake(&self.futex, Some(1)) }.expect("Failed to wake futex");
+    }
+
+    /// Wake all waiting threads
+    pub fn notify_all(&self) {
+        self.futex.fetch_add(1, Ordering::Relaxed);
+
+        unsafe { futex_wake(&self.futex, None) }.expect("Failed to wake futex");
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
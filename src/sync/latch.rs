@@ -0,0 +1,47 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::wait_on;
+use crate::syscalls::futex_wake;
+
+/// Lets threads wait until a number of events happened, e.g. until a set of workers
+/// finished. Unlike a `Barrier`, the threads that count down don't wait.
+pub struct CountDownLatch {
+    count: AtomicU32,
+}
+
+impl CountDownLatch {
+    pub const fn new(count: u32) -> Self {
+        CountDownLatch {
+            count: AtomicU32::new(count),
+        }
+    }
+
+    /// The number of `count_down` calls left until waiters are released
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Record an event, releasing the waiters if it was the last one
+    pub fn count_down(&self) {
+        let previous = self.count.fetch_sub(1, Ordering::Release);
+
+        assert!(previous > 0, "counted down a CountDownLatch below zero");
+
+        if previous == 1 {
+            unsafe { futex_wake(&self.count, None) }.expect("Failed to wake futex");
+        }
+    }
+
+    /// Wait until the count reaches zero
+    pub fn wait(&self) {
+        loop {
+            let count = self.count.load(Ordering::Acquire);
+
+            if count == 0 {
+                return;
+            }
+
+            wait_on(&self.count, count);
+        }
+    }
+}
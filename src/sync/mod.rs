@@ -1,11 +1,57 @@
+mod barrier;
+pub mod channel;
+mod condvar;
+mod latch;
+mod once;
+mod rwlock;
+mod semaphore;
+
+pub use barrier::Barrier;
+pub use channel::{channel, sync_channel};
+pub use condvar::Condvar;
+pub use latch::CountDownLatch;
+pub use once::{Lazy, Once, OnceCell};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+
 use core::{
     cell::UnsafeCell,
     marker::PhantomData,
     ops::{Deref, DerefMut},
     sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::Duration,
 };
 
-use crate::syscalls::{futex_wait, futex_wake, FutexFlags};
+use crate::syscalls::{futex_wait, futex_wake, helper::SyscallErrorKind, FutexFlags, Timespec};
+
+/// Block until `futex` is woken, if it still contains `expected`.
+/// May return spuriously, so callers need to check their condition in a loop.
+fn wait_on(futex: &AtomicU32, expected: u32) {
+    if let Err(err) = unsafe { futex_wait(futex, expected, None, FutexFlags::empty()) } {
+        // `futex` was already changed, or we were interrupted by a signal
+        if err.kind() != SyscallErrorKind::EAGAIN && err.kind() != SyscallErrorKind::EINTR {
+            panic!("Failed to wait on futex: {}", err);
+        }
+    }
+}
+
+/// Like `wait_on`, but gives up after `timeout`. Returns true if it timed out.
+fn wait_on_timeout(futex: &AtomicU32, expected: u32, timeout: Duration) -> bool {
+    let mut time = Timespec::new(
+        timeout.as_secs().min(i64::MAX as u64) as i64,
+        timeout.subsec_nanos() as i64,
+    );
+
+    match unsafe { futex_wait(futex, expected, Some(&mut time), FutexFlags::empty()) } {
+        Err(err) if err.kind() == SyscallErrorKind::ETIMEDOUT => true,
+        Err(err)
+            if err.kind() != SyscallErrorKind::EAGAIN && err.kind() != SyscallErrorKind::EINTR =>
+        {
+            panic!("Failed to wait on futex: {}", err)
+        }
+        _ => false,
+    }
+}
 
 pub struct SpinMutex<T> {
     is_locked: AtomicBool,
@@ -112,14 +158,22 @@ impl<T, const N: usize> FutexMutex<T, N> {
     where
         T: Send + Sync,
     {
-        let mutex_var = &self.is_locked as *const AtomicU32;
+        Self::lock_var(&self.is_locked);
+
+        FutexMutexGuard {
+            mutex_var: &self.is_locked,
+            data: self.data.get(),
+            _phantom: Default::default(),
+        }
+    }
 
+    /// Take the lock that `is_locked` belongs to
+    fn lock_var(is_locked: &AtomicU32) {
         'outer: loop {
             let mut i = 0;
             while i < N {
                 // TODO: at least one of these Orderings can probably be `Aquire`
-                if self
-                    .is_locked
+                if is_locked
                     .compare_exchange_weak(
                         Self::UNLOCKED,
                         Self::LOCKED,
@@ -133,23 +187,21 @@ impl<T, const N: usize> FutexMutex<T, N> {
 
                 i += 1;
 
-                while i < N && self.is_locked.load(Ordering::Relaxed) == Self::LOCKED {
+                while i < N && is_locked.load(Ordering::Relaxed) == Self::LOCKED {
                     core::hint::spin_loop();
                     i += 1;
                 }
             }
 
-            unsafe {
-                let val = (&*mutex_var).load(Ordering::Relaxed);
-                debug_assert!(
-                    (0..=1).contains(&val),
-                    "mutex value was expected to be 0 or 1, but was acutally {}",
-                    val
-                );
-            }
+            let val = is_locked.load(Ordering::Relaxed);
+            debug_assert!(
+                (0..=1).contains(&val),
+                "mutex value was expected to be 0 or 1, but was acutally {}",
+                val
+            );
 
             // Try to wait on the futex
-            let res = unsafe { futex_wait(mutex_var, Self::LOCKED, None, FutexFlags::empty()) };
+            let res = unsafe { futex_wait(is_locked, Self::LOCKED, None, FutexFlags::empty()) };
 
             if let Err(err) = res {
                 if err.0 != 11 {
@@ -163,17 +215,12 @@ impl<T, const N: usize> FutexMutex<T, N> {
                 // Try to aquire the lock.
             }
         }
-
-        FutexMutexGuard {
-            mutex_var,
-            data: self.data.get(),
-            _phantom: Default::default(),
-        }
     }
 
     /// Wait until someone else locks the mutex at least once
     /// If the lock is already locked reutrn immediately
     /// returns if we actually waited
+    /// Use a `Condvar` to wait for a condition instead.
     pub fn wait(&self) -> bool {
         let mutex_var = &self.is_locked as *const AtomicU32;
 
@@ -0,0 +1,241 @@
+use core::{
+    cell::{Cell, UnsafeCell},
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::wait_on;
+use crate::syscalls::futex_wake;
+
+/// Runs an initialization exactly once, even if several threads race to run it.
+/// Threads that lose the race block until the winner is done.
+///
+/// A panic exits the thread without unwinding, so if the initialization panics,
+/// all other threads that wait for it block forever.
+pub struct Once {
+    state: AtomicU32,
+}
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+const COMPLETE: u32 = 2;
+
+impl Once {
+    pub const fn new() -> Self {
+        Once {
+            state: AtomicU32::new(INCOMPLETE),
+        }
+    }
+
+    /// Returns true if an initialization has finished
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Run `f` if no `call_once` has run yet, otherwise wait until it finished
+    pub fn call_once(&self, f: impl FnOnce()) {
+        if self.is_completed() {
+            return;
+        }
+
+        let mut f = Some(f);
+
+        self.call_once_inner(&mut || {
+            (f.take().unwrap())();
+            true
+        });
+    }
+
+    /// `init` returns false if it failed, which lets the next caller try again
+    fn call_once_inner(&self, init: &mut dyn FnMut() -> bool) {
+        let mut state = self.state.load(Ordering::Acquire);
+
+        loop {
+            match state {
+                COMPLETE => return,
+                INCOMPLETE => {
+                    if let Err(new) = self.state.compare_exchange_weak(
+                        INCOMPLETE,
+                        RUNNING,
+                        Ordering::Acquire,
+                        Ordering::Acquire,
+                    ) {
+                        state = new;
+                        continue;
+                    }
+
+                    let done = init();
+
+                    self.state
+                        .store(if done { COMPLETE } else { INCOMPLETE }, Ordering::Release);
+
+                    // After a failed attempt, the waiters try again themselves
+                    unsafe { futex_wake(&self.state, None) }.expect("Failed to wake futex");
+
+                    return;
+                }
+                _ => {
+                    wait_on(&self.state, RUNNING);
+
+                    state = self.state.load(Ordering::Acquire);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A value that is set at most once, e.g. lazily by the first thread that needs it
+pub struct OnceCell<T> {
+    once: Once,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for OnceCell<T> {}
+// `Send` as well, since any thread might initialize the value
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> Self {
+        OnceCell {
+            once: Once::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The value, if it was set
+    pub fn get(&self) -> Option<&T> {
+        // Safety: the value is never changed once it is complete
+        self.once
+            .is_completed()
+            .then(|| unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.once.is_completed() {
+            Some(unsafe { self.value.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Set the value, or return `value` if it was already set
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+
+        self.get_or_init(|| value.take().unwrap());
+
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// Get the value, setting it to `f()` if it is not set yet.
+    /// Only one thread runs `f`, the others wait for its result.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        match self.get_or_try_init(|| Ok::<T, core::convert::Infallible>(f())) {
+            Ok(value) => value,
+            Err(err) => match err {},
+        }
+    }
+
+    /// Like `get_or_init`, but if `f` fails the cell stays empty,
+    /// and the next thread that needs the value tries again
+    pub fn get_or_try_init<E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<&T, E> {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+
+        let mut f = Some(f);
+        let mut res = Ok(());
+
+        self.once
+            .call_once_inner(&mut || match (f.take().unwrap())() {
+                Ok(value) => {
+                    // Safety: we are the only thread running the initialization
+                    unsafe { (*self.value.get()).write(value) };
+                    true
+                }
+                Err(err) => {
+                    res = Err(err);
+                    false
+                }
+            });
+
+        res?;
+
+        // Either we or another thread succeeded, a failure makes the waiters try again
+        Ok(self
+            .get()
+            .expect("OnceCell is not initialized after a successful init"))
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+
+    /// Take the value out, leaving the cell empty
+    pub fn take(&mut self) -> Option<T> {
+        if self.once.is_completed() {
+            self.once = Once::new();
+
+            Some(unsafe { self.value.get_mut().as_ptr().read() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
+
+/// A value that is initialized by the first thread that dereferences it,
+/// e.g. for statics that can't be built in a `const`
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+// Safety: `init` is only taken by the thread that initializes `cell`
+unsafe impl<T, F: Send> Sync for Lazy<T, F> where OnceCell<T>: Sync {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Lazy {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+
+    /// Initialize `this` if that did not happen yet
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(init) => init(),
+            None => unreachable!("Lazy was initialized twice"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
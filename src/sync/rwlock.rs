@@ -0,0 +1,275 @@
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::wait_on;
+use crate::syscalls::futex_wake;
+
+/// A reader-writer lock that blocks on futexes.
+/// Waiting writers keep new readers from taking the lock, so that writers are not
+/// starved by readers that keep overlapping.
+pub struct RwLock<T> {
+    /// The number of readers, or `WRITE_LOCKED`, and the `*_WAITING` bits
+    state: AtomicU32,
+    /// Incremented to wake a writer, so that its wait can't miss the wakeup
+    writer_notify: AtomicU32,
+    data: UnsafeCell<T>,
+    _needs_pin: core::marker::PhantomPinned,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+const READ_LOCKED: u32 = 1;
+const MASK: u32 = (1 << 30) - 1;
+const WRITE_LOCKED: u32 = MASK;
+const MAX_READERS: u32 = MASK - 1;
+const READERS_WAITING: u32 = 1 << 30;
+const WRITERS_WAITING: u32 = 1 << 31;
+
+fn is_unlocked(state: u32) -> bool {
+    state & MASK == 0
+}
+
+/// New readers only get the lock if nobody is waiting for it
+fn is_read_lockable(state: u32) -> bool {
+    state & MASK < MAX_READERS && state & (READERS_WAITING | WRITERS_WAITING) == 0
+}
+
+impl<T> RwLock<T> {
+    /// # Safety: must be pinned
+    pub const unsafe fn new(data: T) -> Self {
+        RwLock {
+            state: AtomicU32::new(0),
+            writer_notify: AtomicU32::new(0),
+            data: UnsafeCell::new(data),
+            _needs_pin: core::marker::PhantomPinned,
+        }
+    }
+
+    /// Lock for shared access, waiting while a writer holds or waits for the lock
+    pub fn read(&self) -> RwLockReadGuard<'_, T>
+    where
+        T: Send + Sync,
+    {
+        let mut state = self.state.load(Ordering::Relaxed);
+
+        loop {
+            if is_read_lockable(state) {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state + READ_LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(new) => {
+                        state = new;
+                        continue;
+                    }
+                }
+            }
+
+            assert!(state & MASK != MAX_READERS, "too many readers on RwLock");
+
+            // Make sure the unlocking thread knows to wake us
+            if state & READERS_WAITING == 0 {
+                if let Err(new) = self.state.compare_exchange_weak(
+                    state,
+                    state | READERS_WAITING,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = new;
+                    continue;
+                }
+            }
+
+            wait_on(&self.state, state | READERS_WAITING);
+
+            state = self.state.load(Ordering::Relaxed);
+        }
+
+        RwLockReadGuard {
+            lock: self,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Lock for exclusive access
+    pub fn write(&self) -> RwLockWriteGuard<'_, T>
+    where
+        T: Send + Sync,
+    {
+        let mut state = self.state.load(Ordering::Relaxed);
+
+        // Once we waited, we can't know if other writers are still waiting,
+        // so we keep `WRITERS_WAITING` set when we get the lock
+        let mut other_writers_waiting = 0;
+
+        loop {
+            if is_unlocked(state) {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state | WRITE_LOCKED | other_writers_waiting,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(new) => {
+                        state = new;
+                        continue;
+                    }
+                }
+            }
+
+            if state & WRITERS_WAITING == 0 {
+                if let Err(new) = self.state.compare_exchange_weak(
+                    state,
+                    state | WRITERS_WAITING,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = new;
+                    continue;
+                }
+            }
+
+            other_writers_waiting = WRITERS_WAITING;
+
+            let seq = self.writer_notify.load(Ordering::Acquire);
+
+            // The lock might have been released before we loaded `seq`
+            state = self.state.load(Ordering::Relaxed);
+            if is_unlocked(state) || state & WRITERS_WAITING == 0 {
+                continue;
+            }
+
+            wait_on(&self.writer_notify, seq);
+
+            state = self.state.load(Ordering::Relaxed);
+        }
+
+        RwLockWriteGuard {
+            lock: self,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn read_unlock(&self) {
+        let state = self.state.fetch_sub(READ_LOCKED, Ordering::Release) - READ_LOCKED;
+
+        // Readers only wait while a writer waits, so the last reader wakes the writer
+        if is_unlocked(state) && state & WRITERS_WAITING != 0 {
+            self.wake_writer_or_readers(state);
+        }
+    }
+
+    fn write_unlock(&self) {
+        let state = self.state.fetch_sub(WRITE_LOCKED, Ordering::Release) - WRITE_LOCKED;
+
+        if state & (READERS_WAITING | WRITERS_WAITING) != 0 {
+            self.wake_writer_or_readers(state);
+        }
+    }
+
+    /// Wake a writer if one is waiting, otherwise all waiting readers.
+    /// `state` must be unlocked.
+    fn wake_writer_or_readers(&self, mut state: u32) {
+        debug_assert!(is_unlocked(state));
+
+        if state == WRITERS_WAITING {
+            match self
+                .state
+                .compare_exchange(state, 0, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    self.wake_writer();
+                    return;
+                }
+                Err(new) => state = new,
+            }
+        }
+
+        if state == READERS_WAITING | WRITERS_WAITING {
+            if self
+                .state
+                .compare_exchange(state, READERS_WAITING, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+            {
+                // Someone took the lock, they will wake the others when they unlock it
+                return;
+            }
+
+            if self.wake_writer() {
+                return;
+            }
+
+            // The writer that set `WRITERS_WAITING` already got the lock some other way
+            state = READERS_WAITING;
+        }
+
+        if state == READERS_WAITING
+            && self
+                .state
+                .compare_exchange(state, 0, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            unsafe { futex_wake(&self.state, None) }.expect("Failed to wake futex");
+        }
+    }
+
+    /// Returns true if a writer was woken
+    fn wake_writer(&self) -> bool {
+        self.writer_notify.fetch_add(1, Ordering::Release);
+
+        unsafe { futex_wake(&self.writer_notify, Some(1)) }.expect("Failed to wake futex") != 0
+    }
+}
+
+pub struct RwLockReadGuard<'d, T> {
+    lock: &'d RwLock<T>,
+    _phantom: PhantomData<&'d T>,
+}
+
+impl<'d, T> Deref for RwLockReadGuard<'d, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'d, T> Drop for RwLockReadGuard<'d, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+pub struct RwLockWriteGuard<'d, T> {
+    lock: &'d RwLock<T>,
+    _phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T> Deref for RwLockWriteGuard<'d, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'d, T> DerefMut for RwLockWriteGuard<'d, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'d, T> Drop for RwLockWriteGuard<'d, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
@@ -0,0 +1,83 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::wait_on;
+use crate::syscalls::futex_wake;
+
+/// A counting semaphore, e.g. to bound how many threads do something at once.
+/// Permits are returned when their `SemaphorePermit` is dropped.
+pub struct Semaphore {
+    permits: AtomicU32,
+    /// The number of threads blocked in `acquire`, so that `release` can skip the syscall
+    waiting: AtomicU32,
+}
+
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Semaphore {
+            permits: AtomicU32::new(permits),
+            waiting: AtomicU32::new(0),
+        }
+    }
+
+    /// The number of permits that can be acquired without blocking
+    pub fn available_permits(&self) -> u32 {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    /// Take a permit, waiting until one is available
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        loop {
+            if let Some(permit) = self.try_acquire() {
+                return permit;
+            }
+
+            // `SeqCst`, so that either we see the permit of a concurrent `release`,
+            // or it sees us waiting
+            self.waiting.fetch_add(1, Ordering::SeqCst);
+
+            if self.permits.load(Ordering::SeqCst) == 0 {
+                wait_on(&self.permits, 0);
+            }
+
+            self.waiting.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Take a permit if one is available
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(SemaphorePermit { semaphore: self }),
+                Err(new) => permits = new,
+            }
+        }
+
+        None
+    }
+
+    fn release(&self) {
+        self.permits.fetch_add(1, Ordering::SeqCst);
+
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            unsafe { futex_wake(&self.permits, Some(1)) }.expect("Failed to wake futex");
+        }
+    }
+}
+
+/// A permit of a `Semaphore`, which is returned when this is dropped
+pub struct SemaphorePermit<'s> {
+    semaphore: &'s Semaphore,
+}
+
+impl<'s> Drop for SemaphorePermit<'s> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
@@ -18,6 +18,11 @@ pub fn write(fd: u32, buf: &[u8]) -> SyscallResult<usize> {
     unsafe { raw::write(fd, buf.as_ptr(), buf.len()) }
 }
 
+/// Wait for events on `fds`. Returns the number of fds with non-empty `revents`.
+pub fn poll(fds: &mut [PollFd], timeout: i32) -> SyscallResult<usize> {
+    unsafe { raw::poll(fds.as_mut_ptr(), fds.len(), timeout) }
+}
+
 bitflags::bitflags! {
     pub struct OpenFlags: i32 {
         const CREAT = 0o100;
@@ -58,6 +63,19 @@ pub fn close(fd: Fd) -> SyscallResult<()> {
     unsafe { raw::close(fd.0).map(|_| ()) }
 }
 
+/// Get the status flags of the open file `fd` refers to
+pub fn get_status_flags(fd: Fd) -> SyscallResult<OpenFlags> {
+    let flags = unsafe { fcntl(fd.0, FcntlCmd::GetFl, 0)? };
+
+    Ok(OpenFlags::from_bits_truncate(flags as i32))
+}
+
+/// Set the status flags of the open file `fd` refers to.
+/// Only `APPEND`, `ASYNC`, `DIRECT`, `NOATIME` and `NONBLOCK` can be changed.
+pub fn set_status_flags(fd: Fd, flags: OpenFlags) -> SyscallResult<()> {
+    unsafe { fcntl(fd.0, FcntlCmd::SetFl, flags.bits() as usize).map(|_| ()) }
+}
+
 #[repr(i32)]
 pub enum FutexOp {
     Wait = 0,
@@ -102,7 +120,8 @@ pub unsafe fn futex_wait(
 pub unsafe fn futex_wake(uaddr: FutexVar, n: Option<u32>) -> SyscallResult<u64> {
     let op = FutexOp::Wake as i32;
 
-    let val = n.unwrap_or(u32::MAX);
+    // The kernel reads this as an `int`, so `u32::MAX` would wake only one waiter
+    let val = n.unwrap_or(i32::MAX as u32);
 
     raw::futex(
         uaddr,
@@ -233,3 +252,14 @@ pub fn sleep(duration: core::time::Duration) -> SyscallResult<()> {
 
     Ok(())
 }
+
+/// Read the current time of `clock`
+pub fn clock_gettime(clock: ClockId) -> SyscallResult<core::time::Duration> {
+    let mut time = Timespec::default();
+
+    unsafe {
+        raw::clock_gettime(clock, &mut time as *mut _)?;
+    }
+
+    Ok(time.into())
+}
@@ -12,22 +12,38 @@ pub const SYS_NO_READ: usize = 0;
 pub const SYS_NO_WRITE: usize = 1;
 pub const SYS_NO_OPEN: usize = 2;
 pub const SYS_NO_CLOSE: usize = 3;
+pub const SYS_NO_POLL: usize = 7;
 pub const SYS_NO_MMAP: usize = 9;
+pub const SYS_NO_MPROTECT: usize = 10;
 pub const SYS_NO_MUNMAP: usize = 11;
 pub const SYS_NO_BRK: usize = 12;
 pub const SYS_NO_RT_SIGACTION: usize = 13;
+pub const SYS_NO_MREMAP: usize = 25;
+pub const SYS_NO_MADVISE: usize = 28;
 pub const SYS_NO_NANOSLEEP: usize = 35;
 pub const SYS_NO_CLONE: usize = 56;
 pub const SYS_NO_FORK: usize = 57;
 pub const SYS_NO_EXIT: usize = 60;
 pub const SYS_NO_WAIT4: usize = 61;
+pub const SYS_NO_FCNTL: usize = 72;
 pub const SYS_NO_GETRLIMIT: usize = 97;
 pub const SYS_NO_SIGALTSTACK: usize = 131;
 pub const SYS_NO_ARCH_PTRCTL: usize = 158;
 pub const SYS_NO_SETRLIMIT: usize = 160;
 pub const SYS_NO_GETTID: usize = 186;
 pub const SYS_NO_FUTEX: usize = 202;
+pub const SYS_NO_CLOCK_GETTIME: usize = 228;
+pub const SYS_NO_EPOLL_WAIT: usize = 232;
+pub const SYS_NO_EPOLL_CTL: usize = 233;
 pub const SYS_NO_WAITID: usize = 247;
+pub const SYS_NO_TIMERFD_CREATE: usize = 283;
+pub const SYS_NO_TIMERFD_SETTIME: usize = 286;
+pub const SYS_NO_TIMERFD_GETTIME: usize = 287;
+pub const SYS_NO_EVENTFD2: usize = 290;
+pub const SYS_NO_EPOLL_CREATE1: usize = 291;
+pub const SYS_NO_IO_URING_SETUP: usize = 425;
+pub const SYS_NO_IO_URING_ENTER: usize = 426;
+pub const SYS_NO_IO_URING_REGISTER: usize = 427;
 pub const SYS_NO_CLONE3: usize = 435;
 
 pub unsafe fn read(fd: u32, buf: *mut u8, count: usize) -> SyscallResult<usize> {
@@ -45,6 +61,37 @@ pub unsafe fn close(fd: u32) -> SyscallResult<usize> {
     syscall!(SYS_NO_CLOSE, fd)
 }
 
+bitflags! {
+    pub struct PollEvents: i16 {
+        /// There is data to read
+        const IN = 0x001;
+        /// There is urgent data to read
+        const PRI = 0x002;
+        /// Writing is now possible
+        const OUT = 0x004;
+        /// Error condition (only returned)
+        const ERR = 0x008;
+        /// Hang up (only returned)
+        const HUP = 0x010;
+        /// The fd is not open (only returned)
+        const NVAL = 0x020;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PollFd {
+    pub fd: u32,
+    pub events: PollEvents,
+    pub revents: PollEvents,
+}
+
+/// `timeout` is in milliseconds, -1 waits indefinitely
+#[inline(always)]
+pub unsafe fn poll(fds: *mut PollFd, nfds: usize, timeout: i32) -> SyscallResult<usize> {
+    syscall!(SYS_NO_POLL, fds, nfds, timeout)
+}
+
 bitflags! {
     pub struct MProt: u64 {
         const NONE = 0;
@@ -75,6 +122,10 @@ bitflags! {
         const HUGETLB = 0x40000;
         const SYNC = 0x80000;
         const FIXED_NOREPLACE = 0x100000;
+        /// With `HUGETLB`: use 2 MiB pages instead of the system's default huge page size
+        const HUGE_2MB = 21 << 26;
+        /// With `HUGETLB`: use 1 GiB pages instead of the system's default huge page size
+        const HUGE_1GB = 30 << 26;
     }
 }
 
@@ -101,6 +152,44 @@ pub unsafe fn munmap(addr: *mut u8, len: usize) -> SyscallResult<usize> {
     syscall!(SYS_NO_MUNMAP, addr, len)
 }
 
+pub unsafe fn mprotect(addr: *mut u8, len: usize, prot: MProt) -> SyscallResult<usize> {
+    syscall!(SYS_NO_MPROTECT, addr, len, prot.bits())
+}
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug)]
+pub enum MAdvice {
+    Normal = 0,
+    Random = 1,
+    Sequential = 2,
+    WillNeed = 3,
+    DontNeed = 4,
+    Free = 8,
+    HugePage = 14,
+    NoHugePage = 15,
+}
+
+pub unsafe fn madvise(addr: *mut u8, len: usize, advice: MAdvice) -> SyscallResult<usize> {
+    syscall!(SYS_NO_MADVISE, addr, len, advice as usize)
+}
+
+bitflags! {
+    pub struct MRemapFlags: u64 {
+        const MAYMOVE = 1;
+        const FIXED = 2;
+        const DONTUNMAP = 4;
+    }
+}
+
+pub unsafe fn mremap(
+    old_addr: *mut u8,
+    old_len: usize,
+    new_len: usize,
+    flags: MRemapFlags,
+) -> SyscallResult<*mut u8> {
+    syscall!(SYS_NO_MREMAP, old_addr, old_len, new_len, flags.bits())
+}
+
 pub unsafe fn brk(brk: *const u8) -> SyscallResult<*const u8> {
     syscall!(SYS_NO_BRK, brk)
 }
@@ -753,6 +842,21 @@ impl Timespec {
     }
 }
 
+impl From<core::time::Duration> for Timespec {
+    fn from(duration: core::time::Duration) -> Self {
+        Self::new(
+            duration.as_secs().min(i64::MAX as u64) as i64,
+            duration.subsec_nanos() as i64,
+        )
+    }
+}
+
+impl From<Timespec> for core::time::Duration {
+    fn from(timespec: Timespec) -> Self {
+        Self::new(timespec.seconds as u64, timespec.nano_seconds as u32)
+    }
+}
+
 #[inline(always)]
 pub unsafe fn futex(
     uaddr: *const AtomicU32,
@@ -795,3 +899,402 @@ pub unsafe fn waitid(
 ) -> SyscallResult<u64> {
     syscall!(SYS_NO_WAITID, which, upid, infop, options.bits(), ru)
 }
+
+bitflags! {
+    pub struct EpollCreateFlags: i32 {
+        const CLOEXEC = 0o2000000;
+    }
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, Debug)]
+pub enum EpollOp {
+    Add = 1,
+    Del = 2,
+    Mod = 3,
+}
+
+bitflags! {
+    pub struct EpollFlags: u32 {
+        /// The associated file is available for read operations
+        const IN = 0x001;
+        /// There is an exceptional condition on the file descriptor
+        const PRI = 0x002;
+        /// The associated file is available for write operations
+        const OUT = 0x004;
+        /// Error condition happened on the associated file descriptor
+        const ERR = 0x008;
+        /// Hang up happened on the associated file descriptor
+        const HUP = 0x010;
+        const RDNORM = 0x040;
+        const RDBAND = 0x080;
+        const WRNORM = 0x100;
+        const WRBAND = 0x200;
+        const MSG = 0x400;
+        /// Stream socket peer closed connection, or shut down writing half of connection
+        const RDHUP = 0x2000;
+        /// Sets an exclusive wakeup mode for the epoll file descriptor
+        const EXCLUSIVE = 1 << 28;
+        /// Prevent system suspend while the event is pending
+        const WAKEUP = 1 << 29;
+        /// Disable the file descriptor after one event was received
+        const ONESHOT = 1 << 30;
+        /// Use edge-triggered notification
+        const ET = 1 << 31;
+    }
+}
+
+/// NOTE: this struct is packed on x86_64
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct EpollEvent {
+    pub events: EpollFlags,
+    pub data: u64,
+}
+
+impl EpollEvent {
+    pub const fn empty() -> Self {
+        Self {
+            events: EpollFlags::empty(),
+            data: 0,
+        }
+    }
+}
+
+#[inline(always)]
+pub unsafe fn epoll_create1(flags: EpollCreateFlags) -> SyscallResult<u32> {
+    syscall!(SYS_NO_EPOLL_CREATE1, flags.bits())
+}
+
+#[inline(always)]
+pub unsafe fn epoll_ctl(
+    epfd: u32,
+    op: EpollOp,
+    fd: u32,
+    event: *mut EpollEvent,
+) -> SyscallResult<()> {
+    syscall!(SYS_NO_EPOLL_CTL, epfd, op, fd, event).map(|_: usize| ())
+}
+
+/// `timeout` is in milliseconds, -1 waits indefinitely
+#[inline(always)]
+pub unsafe fn epoll_wait(
+    epfd: u32,
+    events: *mut EpollEvent,
+    max_events: i32,
+    timeout: i32,
+) -> SyscallResult<usize> {
+    syscall!(SYS_NO_EPOLL_WAIT, epfd, events, max_events, timeout)
+}
+
+bitflags! {
+    pub struct EventFdFlags: i32 {
+        const SEMAPHORE = 1;
+        const NONBLOCK = 0o4000;
+        const CLOEXEC = 0o2000000;
+    }
+}
+
+#[inline(always)]
+pub unsafe fn eventfd2(initval: u32, flags: EventFdFlags) -> SyscallResult<u32> {
+    syscall!(SYS_NO_EVENTFD2, initval, flags.bits())
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, Debug)]
+pub enum ClockId {
+    Realtime = 0,
+    Monotonic = 1,
+    ProcessCputime = 2,
+    ThreadCputime = 3,
+    MonotonicRaw = 4,
+    RealtimeCoarse = 5,
+    MonotonicCoarse = 6,
+    Boottime = 7,
+}
+
+#[inline(always)]
+pub unsafe fn clock_gettime(clock: ClockId, tp: *mut Timespec) -> SyscallResult<()> {
+    syscall!(SYS_NO_CLOCK_GETTIME, clock, tp).map(|_: usize| ())
+}
+
+bitflags! {
+    pub struct TimerFdFlags: i32 {
+        const NONBLOCK = 0o4000;
+        const CLOEXEC = 0o2000000;
+    }
+}
+
+bitflags! {
+    pub struct TimerFdSetFlags: i32 {
+        /// `value` is an absolute time instead of being relative to the current time
+        const ABSTIME = 1;
+        const CANCEL_ON_SET = 2;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ItimerSpec {
+    /// Interval for periodic timers
+    pub interval: Timespec,
+    /// Initial expiration. Zero disarms the timer.
+    pub value: Timespec,
+}
+
+#[inline(always)]
+pub unsafe fn timerfd_create(clock: ClockId, flags: TimerFdFlags) -> SyscallResult<u32> {
+    syscall!(SYS_NO_TIMERFD_CREATE, clock, flags.bits())
+}
+
+#[inline(always)]
+pub unsafe fn timerfd_settime(
+    fd: u32,
+    flags: TimerFdSetFlags,
+    new_value: *const ItimerSpec,
+    old_value: *mut ItimerSpec,
+) -> SyscallResult<()> {
+    syscall!(SYS_NO_TIMERFD_SETTIME, fd, flags.bits(), new_value, old_value).map(|_: usize| ())
+}
+
+#[inline(always)]
+pub unsafe fn timerfd_gettime(fd: u32, curr_value: *mut ItimerSpec) -> SyscallResult<()> {
+    syscall!(SYS_NO_TIMERFD_GETTIME, fd, curr_value).map(|_: usize| ())
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug)]
+pub enum FcntlCmd {
+    /// Get the file status flags. Returns the flags.
+    GetFl = 3,
+    /// Set the file status flags to `arg`
+    SetFl = 4,
+}
+
+#[inline(always)]
+pub unsafe fn fcntl(fd: u32, cmd: FcntlCmd, arg: usize) -> SyscallResult<usize> {
+    syscall!(SYS_NO_FCNTL, fd, cmd, arg)
+}
+
+bitflags! {
+    pub struct IoUringSetupFlags: u32 {
+        /// Busy-wait for completions
+        const IOPOLL = 1 << 0;
+        /// Let a kernel thread poll the submission queue
+        const SQPOLL = 1 << 1;
+        /// Bind the `SQPOLL` thread to `sq_thread_cpu`
+        const SQ_AFF = 1 << 2;
+        /// Use `cq_entries` as the size of the completion queue
+        const CQSIZE = 1 << 3;
+        const CLAMP = 1 << 4;
+        const ATTACH_WQ = 1 << 5;
+        const R_DISABLED = 1 << 6;
+    }
+}
+
+bitflags! {
+    pub struct IoUringFeatures: u32 {
+        /// Both rings are covered by a single mapping at `IORING_OFF_SQ_RING`
+        const SINGLE_MMAP = 1 << 0;
+        /// Completions are never dropped if the completion queue is full
+        const NODROP = 1 << 1;
+        /// Data passed by pointer only has to be valid until the entry is submitted
+        const SUBMIT_STABLE = 1 << 2;
+        /// An offset of -1 reads and writes at the current file position
+        const RW_CUR_POS = 1 << 3;
+        const CUR_PERSONALITY = 1 << 4;
+        const FAST_POLL = 1 << 5;
+        const POLL_32BITS = 1 << 6;
+    }
+}
+
+bitflags! {
+    pub struct IoUringEnterFlags: u32 {
+        /// Wait for `min_complete` completions
+        const GETEVENTS = 1 << 0;
+        /// Wake the `SQPOLL` thread
+        const SQ_WAKEUP = 1 << 1;
+        /// Wait for space in the submission queue
+        const SQ_WAIT = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct IoUringSqeFlags: u8 {
+        /// `fd` is an index into the registered files
+        const FIXED_FILE = 1 << 0;
+        /// Start once all previous entries completed
+        const IO_DRAIN = 1 << 1;
+        /// Start the next entry once this one completed successfully
+        const IO_LINK = 1 << 2;
+        /// Like `IO_LINK`, but the next entry also starts if this one failed
+        const IO_HARDLINK = 1 << 3;
+        /// Always execute in a kernel worker
+        const ASYNC = 1 << 4;
+    }
+}
+
+/// The mmap offset of the submission queue ring
+pub const IORING_OFF_SQ_RING: u64 = 0;
+/// The mmap offset of the completion queue ring
+pub const IORING_OFF_CQ_RING: u64 = 0x8000000;
+/// The mmap offset of the submission queue entries
+pub const IORING_OFF_SQES: u64 = 0x10000000;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoUringOp {
+    Nop = 0,
+    Readv = 1,
+    Writev = 2,
+    Fsync = 3,
+    ReadFixed = 4,
+    WriteFixed = 5,
+    PollAdd = 6,
+    PollRemove = 7,
+    SyncFileRange = 8,
+    SendMsg = 9,
+    RecvMsg = 10,
+    Timeout = 11,
+    TimeoutRemove = 12,
+    Accept = 13,
+    AsyncCancel = 14,
+    LinkTimeout = 15,
+    Connect = 16,
+    Fallocate = 17,
+    OpenAt = 18,
+    Close = 19,
+    FilesUpdate = 20,
+    Statx = 21,
+    Read = 22,
+    Write = 23,
+    Fadvise = 24,
+    Madvise = 25,
+    Send = 26,
+    Recv = 27,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug)]
+pub enum IoUringRegisterOp {
+    RegisterBuffers = 0,
+    UnregisterBuffers = 1,
+    RegisterFiles = 2,
+    UnregisterFiles = 3,
+    RegisterEventFd = 4,
+    UnregisterEventFd = 5,
+}
+
+/// Offsets of the fields of the submission queue ring in its mapping
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IoSqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub flags: u32,
+    pub dropped: u32,
+    pub array: u32,
+    pub resv1: u32,
+    pub resv2: u64,
+}
+
+/// Offsets of the fields of the completion queue ring in its mapping
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IoCqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub overflow: u32,
+    pub cqes: u32,
+    pub flags: u32,
+    pub resv1: u32,
+    pub resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IoUringParams {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    /// `IoUringSetupFlags`
+    pub flags: u32,
+    pub sq_thread_cpu: u32,
+    pub sq_thread_idle: u32,
+    /// `IoUringFeatures`, set by the kernel
+    pub features: u32,
+    pub wq_fd: u32,
+    pub resv: [u32; 3],
+    pub sq_off: IoSqringOffsets,
+    pub cq_off: IoCqringOffsets,
+}
+
+/// A submission queue entry
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct IoUringSqe {
+    pub opcode: IoUringOp,
+    pub flags: IoUringSqeFlags,
+    pub ioprio: u16,
+    pub fd: i32,
+    /// The file offset, or `addr2`
+    pub off: u64,
+    /// The buffer address
+    pub addr: u64,
+    pub len: u32,
+    /// Flags specific to `opcode`, e.g. `rw_flags` or `fsync_flags`
+    pub op_flags: u32,
+    /// Passed through to the `IoUringCqe`
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub splice_fd_in: i32,
+    pub pad: [u64; 2],
+}
+
+/// A completion queue entry
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct IoUringCqe {
+    pub user_data: u64,
+    /// The result of the operation, or a negated errno
+    pub res: i32,
+    pub flags: u32,
+}
+
+#[inline(always)]
+pub unsafe fn io_uring_setup(entries: u32, params: *mut IoUringParams) -> SyscallResult<u32> {
+    syscall!(SYS_NO_IO_URING_SETUP, entries, params)
+}
+
+/// Returns the number of submitted entries
+#[inline(always)]
+pub unsafe fn io_uring_enter(
+    fd: u32,
+    to_submit: u32,
+    min_complete: u32,
+    flags: IoUringEnterFlags,
+) -> SyscallResult<u32> {
+    syscall!(
+        SYS_NO_IO_URING_ENTER,
+        fd,
+        to_submit,
+        min_complete,
+        flags.bits(),
+        0usize,
+        0usize
+    )
+}
+
+#[inline(always)]
+pub unsafe fn io_uring_register(
+    fd: u32,
+    op: IoUringRegisterOp,
+    arg: *const (),
+    nr_args: u32,
+) -> SyscallResult<u32> {
+    syscall!(SYS_NO_IO_URING_REGISTER, fd, op, arg, nr_args)
+}
@@ -1,17 +1,38 @@
 use crate::{
     env::Environment,
+    executor::{
+        self,
+        channel::{broadcast, mpsc, oneshot, RecvError, SendError, TrySendError},
+        deque,
+        reactor::Registration,
+        Elapsed, JoinError, LocalExecutor,
+    },
     ffi::const_cstr,
     io::*,
-    sync::Mutex,
-    syscalls::{OpenFlags, OpenMode},
+    sync::{
+        self, Barrier, Condvar, CountDownLatch, Lazy, Mutex, Once, OnceCell, RwLock, Semaphore,
+    },
+    syscalls::{helper::SyscallErrorKind, OpenFlags, OpenMode},
+};
+use alloc::{boxed::Box, collections::LinkedList, rc::Rc, string::String, sync::Arc, vec::Vec};
+use core::{
+    arch::asm,
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::Duration,
 };
-use alloc::{boxed::Box, sync::Arc, vec::Vec};
-use core::{time::Duration, arch::asm};
 
 pub enum TestFunction {
     Alloc,
     ThreadingAndMutex,
     Async,
+    ExecutorBench,
+    AllocBench,
+    /// Needs `RuntimeOptions::with_debug_alloc`
+    AllocDebug,
     UserInput,
     FsTest,
     StackOverflow,
@@ -22,6 +43,9 @@ pub unsafe fn main(env: Environment, test_function: TestFunction) -> i8 {
         TestFunction::Alloc => alloc_test_main(env),
         TestFunction::ThreadingAndMutex => thread_test_main(env),
         TestFunction::Async => async_test_main(env),
+        TestFunction::ExecutorBench => executor_bench_main(env),
+        TestFunction::AllocBench => alloc_bench_main(env),
+        TestFunction::AllocDebug => alloc_debug_main(env),
         TestFunction::UserInput => user_input_main(env),
         TestFunction::FsTest => fs_test_main(env),
         TestFunction::StackOverflow => stack_overflow_test(env),
@@ -108,27 +132,727 @@ unsafe fn stack_overflow_test(_env: Environment) -> i8 {
     0
 }
 
-unsafe fn async_test_main(env: Environment) -> i8 {
+unsafe fn async_test_main(_env: Environment) -> i8 {
     let executor = crate::executor::init(1);
 
-    executor.block_on(async_test_main_inner(env))
+    let res = executor.block_on(async_test_main_inner());
+
+    let handle = executor.spawn(async { 21 * 2 });
+    assert_eq!(executor.block_on(handle), Ok(42));
+
+    let handle = executor.spawn(core::future::pending::<()>());
+    handle.abort();
+    assert_eq!(executor.block_on(handle), Err(JoinError::Aborted));
+
+    let handle = executor.spawn(core::future::pending::<()>());
+    drop(executor);
+    assert!(handle.is_finished());
+    assert_eq!(
+        executor_free_poll(handle),
+        Poll::Ready(Err(JoinError::Shutdown))
+    );
+
+    info!("join handles work");
+
+    // The same tests, on the current thread without any workers
+    assert_eq!(executor::block_on(async_test_main_inner()), res);
+
+    let local = LocalExecutor::new();
+
+    // `Rc` is not `Send`
+    let counter = Rc::new(Cell::new(0));
+
+    let handles: Vec<_> = (0..3)
+        .map(|i| {
+            let counter = counter.clone();
+
+            local.spawn_local(async move {
+                executor::sleep(Duration::from_millis(10 * i)).await;
+                counter.set(counter.get() + 1);
+
+                i
+            })
+        })
+        .collect();
+
+    let spawner = local.spawner();
+    let sum = local.block_on(async move {
+        let nested = spawner.spawn_local(async { 21 * 2 });
+
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await.unwrap();
+        }
+
+        sum + nested.await.unwrap()
+    });
+    assert_eq!(sum, 45);
+    assert_eq!(counter.get(), 3);
+
+    let handle = local.spawn_local(core::future::pending::<()>());
+    handle.abort();
+    assert_eq!(local.block_on(handle), Err(JoinError::Aborted));
+
+    let handle = local.spawn_local(core::future::pending::<()>());
+    drop(local);
+    assert!(handle.is_finished());
+    assert_eq!(
+        executor_free_poll(handle),
+        Poll::Ready(Err(JoinError::Shutdown))
+    );
+
+    info!("local executor works");
+
+    res
 }
 
-async fn async_test_main_inner(_env: Environment) -> i8 {
+unsafe fn executor_bench_main(_env: Environment) -> i8 {
+    let n_threads = ncpu().unwrap();
+
+    // Run the same workload with the work-stealing deques and the
+    // `Mutex<LinkedList>` queues they replaced
+    let deques = executor_bench(executor::init(n_threads));
+    let locked = executor_bench(executor::init_with_locked_queues(n_threads));
+
+    info!(
+        "executor on {} workers: work-stealing deques: {:.0} tasks/s, Mutex<LinkedList>: {:.0} tasks/s ({:.2}x)",
+        n_threads,
+        deques,
+        locked,
+        deques / locked,
+    );
+
+    // Compare the queues on their own, without the rest of the executor
+    let deque = queue_bench::<deque::Worker<usize>>(n_threads);
+    let mutex = queue_bench::<MutexQueue>(n_threads);
+
+    info!(
+        "work-stealing deque: {:?}, Mutex<LinkedList>: {:?} ({:.2}x)",
+        deque,
+        mutex,
+        mutex.as_secs_f64() / deque.as_secs_f64()
+    );
+
+    0
+}
+
+/// Spawn lots of tiny tasks on `executor` and wait for all of them to finish.
+/// Returns the number of tasks run per second.
+fn executor_bench(executor: executor::Executor) -> f64 {
+    const N_TASKS: usize = 2_000_000;
+
+    let done = Arc::new(AtomicUsize::new(0));
+
+    let start = executor::now();
+
+    for _ in 0..N_TASKS {
+        let done = done.clone();
+
+        executor
+            .spawn(async move {
+                done.fetch_add(1, Ordering::Relaxed);
+            })
+            .detach();
+    }
+
+    let spawned = executor::now() - start;
+
+    while done.load(Ordering::Relaxed) < N_TASKS {
+        crate::syscalls::sleep(Duration::from_millis(1)).unwrap();
+    }
+
+    let elapsed = executor::now() - start;
+
+    drop(executor);
+
+    info!(
+        "ran {} tasks in {:?} (spawning took {:?})",
+        N_TASKS, elapsed, spawned,
+    );
+
+    N_TASKS as f64 / elapsed.as_secs_f64()
+}
+
+/// A worker's queue, which the worker pushes to and pops from, while idle workers
+/// steal from it. Stealing takes half of the items, like both executor designs do.
+trait BenchQueue: Sized + 'static {
+    type Stealer: Clone + Unpin + 'static;
+
+    fn new() -> (Self, Self::Stealer);
+    fn push(&self, item: Arc<usize>);
+    fn pop(&self) -> Option<Arc<usize>>;
+    /// Move half of the items from `stealer` into `self`.
+    /// Returns the number of items taken.
+    fn steal_into(&self, stealer: &Self::Stealer) -> usize;
+}
+
+impl BenchQueue for deque::Worker<usize> {
+    type Stealer = deque::Stealer<usize>;
+
+    fn new() -> (Self, Self::Stealer) {
+        deque::new()
+    }
+
+    fn push(&self, item: Arc<usize>) {
+        // can't be full, because we empty it after every round
+        assert!(deque::Worker::push(self, item).is_ok());
+    }
+
+    fn pop(&self) -> Option<Arc<usize>> {
+        deque::Worker::pop(self)
+    }
+
+    fn steal_into(&self, stealer: &Self::Stealer) -> usize {
+        let before = self.len();
+
+        match stealer.steal_batch(self) {
+            deque::Steal::Success(_) => 1 + self.len() - before,
+            _ => 0,
+        }
+    }
+}
+
+type MutexQueue = Pin<Arc<Mutex<LinkedList<Arc<usize>>>>>;
+
+impl BenchQueue for MutexQueue {
+    type Stealer = MutexQueue;
+
+    fn new() -> (Self, Self::Stealer) {
+        // Safety: The Mutex is `Pin`ned by the `Arc::pin`.
+        let queue = unsafe { Arc::pin(Mutex::new(LinkedList::new())) };
+        (queue.clone(), queue)
+    }
+
+    fn push(&self, item: Arc<usize>) {
+        self.lock().push_back(item);
+    }
+
+    fn pop(&self) -> Option<Arc<usize>> {
+        self.lock().pop_front()
+    }
+
+    fn steal_into(&self, stealer: &Self::Stealer) -> usize {
+        // This is how the executor used to steal
+        let mut queue = stealer.lock();
+        let n = queue.len();
+        let mut stolen = queue.split_off(n / 2);
+        drop(queue);
+
+        let n = stolen.len();
+        self.lock().append(&mut stolen);
+
+        n
+    }
+}
+
+/// Push items through a queue in rounds, while `n_threads - 1` threads steal from it
+/// into queues of their own, and work through what they stole
+fn queue_bench<Q: BenchQueue>(n_threads: usize) -> Duration {
+    const N_ROUNDS: usize = 10_000;
+    const ROUND_SIZE: usize = 128;
+
+    let (queue, stealer) = Q::new();
+
+    let done = Arc::new(AtomicBool::new(false));
+
+    let start = executor::now();
+
+    let thieves: Vec<_> = (1..n_threads.max(2))
+        .map(|_| {
+            let stealer = stealer.clone();
+            let done = done.clone();
+
+            crate::thread::spawn(
+                move || {
+                    let (own, _) = Q::new();
+
+                    let mut stolen = 0;
+
+                    while !done.load(Ordering::Relaxed) {
+                        stolen += own.steal_into(&stealer);
+
+                        while own.pop().is_some() {}
+                    }
+
+                    stolen
+                },
+                None,
+            )
+            .expect("Failed to spawn thread")
+        })
+        .collect();
+
+    let item = Arc::new(0);
+
+    let mut popped = 0;
+    for _ in 0..N_ROUNDS {
+        for _ in 0..ROUND_SIZE {
+            queue.push(item.clone());
+        }
+
+        while queue.pop().is_some() {
+            popped += 1;
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+
+    let stolen: usize = thieves
+        .into_iter()
+        .map(|mut thief| thief.join().unwrap().unwrap())
+        .sum();
+
+    assert_eq!(popped + stolen, N_ROUNDS * ROUND_SIZE);
+
+    executor::now() - start
+}
+
+/// Poll a future once, outside of any executor
+fn executor_free_poll<F: Future + Unpin>(mut fut: F) -> Poll<F::Output> {
+    unsafe fn noop_clone(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &NOOP_VTABLE)
+    }
+    unsafe fn noop(_: *const ()) {}
+
+    static NOOP_VTABLE: RawWakerVTable = RawWakerVTable::new(noop_clone, noop, noop, noop);
+
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &NOOP_VTABLE)) };
+
+    Pin::new(&mut fut).poll(&mut Context::from_waker(&waker))
+}
+
+async fn async_test_main_inner() -> i8 {
+    /// Wakes itself while it is being polled
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Is woken by another thread after the task was parked
+    struct WakeFromThread(Option<Arc<AtomicBool>>);
+
+    impl Future for WakeFromThread {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if let Some(done) = self.0.as_ref() {
+                if done.load(Ordering::Acquire) {
+                    return Poll::Ready(());
+                }
+            } else {
+                let done = Arc::new(AtomicBool::new(false));
+                self.0 = Some(done.clone());
+
+                let waker = cx.waker().clone();
+                crate::thread::spawn(
+                    move || {
+                        crate::syscalls::sleep(Duration::from_millis(100)).unwrap();
+                        done.store(true, Ordering::Release);
+                        waker.wake();
+                    },
+                    None,
+                )
+                .expect("Failed to spawn thread");
+            }
+
+            Poll::Pending
+        }
+    }
+
+    for _ in 0..3 {
+        YieldNow(false).await;
+    }
+
+    info!("yielded");
+
+    WakeFromThread(None).await;
+
+    info!("woken by thread");
+
+    // Wait for an fd to become readable
+    let event_fd = unsafe {
+        crate::syscalls::eventfd2(
+            0,
+            crate::syscalls::EventFdFlags::NONBLOCK | crate::syscalls::EventFdFlags::CLOEXEC,
+        )
+    }
+    .map(Fd)
+    .expect("Failed to create eventfd");
+
+    let registration = Arc::new(Registration::new(event_fd).expect("Failed to register eventfd"));
+
+    // A second task waiting on the same fd has to be woken as well
+    let mut waiter = {
+        let registration = registration.clone();
+
+        crate::thread::spawn(
+            move || {
+                executor::block_on(async move {
+                    registration.readable().await;
+                })
+            },
+            None,
+        )
+        .expect("Failed to spawn thread")
+    };
+
+    crate::thread::spawn(
+        move || {
+            crate::syscalls::sleep(Duration::from_millis(100)).unwrap();
+            event_fd.write(&1u64.to_ne_bytes()).unwrap();
+        },
+        None,
+    )
+    .expect("Failed to spawn thread");
+
+    let mut buf = [0; 8];
+    let res = loop {
+        let readiness = registration.readable().await;
+
+        match crate::syscalls::read(event_fd.0, &mut buf) {
+            Err(err) if err.kind() == SyscallErrorKind::EAGAIN => {
+                registration.clear_readiness(readiness)
+            }
+            res => break res,
+        }
+    };
+
+    assert_eq!(res.unwrap(), 8);
+    assert_eq!(u64::from_ne_bytes(buf), 1);
+
+    assert!(waiter.join().unwrap().is_some());
+
+    drop(registration);
+    crate::syscalls::close(event_fd).unwrap();
+
+    info!("woken by reactor");
+
+    let start = executor::now();
+    executor::sleep(Duration::from_millis(50)).await;
+    assert!(executor::now() - start >= Duration::from_millis(50));
+
+    let mut interval = executor::interval(Duration::from_millis(20));
+    let first = interval.tick().await;
+    for i in 1..=3 {
+        let tick = interval.tick().await;
+        assert!(executor::now() >= tick);
+        assert!(tick - first >= Duration::from_millis(20) * i);
+    }
+
+    assert_eq!(
+        executor::timeout(core::future::pending::<()>(), Duration::from_millis(20)).await,
+        Err(Elapsed)
+    );
+    assert_eq!(
+        executor::timeout(async { 42 }, Duration::from_millis(20)).await,
+        Ok(42)
+    );
+    // `Duration::MAX` means no timeout
+    assert_eq!(executor::timeout(async { 42 }, Duration::MAX).await, Ok(42));
+    assert_eq!(
+        executor::timeout(executor::sleep(Duration::MAX), Duration::from_millis(20)).await,
+        Err(Elapsed)
+    );
+
+    info!("timers work");
+
+    // `into_async` sets `NONBLOCK` itself
+    let event_fd = unsafe { crate::syscalls::eventfd2(0, crate::syscalls::EventFdFlags::CLOEXEC) }
+        .map(Fd)
+        .expect("Failed to create eventfd");
+
+    let async_fd = event_fd.into_async().expect("Failed to register eventfd");
+
+    crate::thread::spawn(
+        move || {
+            crate::syscalls::sleep(Duration::from_millis(50)).unwrap();
+            event_fd.write(&2u64.to_ne_bytes()).unwrap();
+        },
+        None,
+    )
+    .expect("Failed to spawn thread");
+
+    let mut buf = [0; 8];
+    assert_eq!(async_fd.read_exact(&mut buf).await.unwrap(), 8);
+    assert_eq!(u64::from_ne_bytes(buf), 2);
+
+    assert_eq!(async_fd.write_all(&3u64.to_ne_bytes()).await.unwrap(), 8);
+    assert_eq!(async_fd.read(&mut buf).await.unwrap().get(), 8);
+    assert_eq!(u64::from_ne_bytes(buf), 3);
+
+    // Blocking reads wait, even though the `AsyncFd` made the fd non-blocking
+    crate::thread::spawn(
+        move || {
+            crate::syscalls::sleep(Duration::from_millis(50)).unwrap();
+            event_fd.write(&4u64.to_ne_bytes()).unwrap();
+        },
+        None,
+    )
+    .expect("Failed to spawn thread");
+
+    assert_eq!(event_fd.read(&mut buf).unwrap().get(), 8);
+    assert_eq!(u64::from_ne_bytes(buf), 4);
+
+    drop(async_fd);
+    crate::syscalls::close(event_fd).unwrap();
+
+    // Regular files can't be registered with epoll, but are always ready
+    let mut file = crate::fs::File::open(
+        const_cstr!("/proc/cpuinfo"),
+        OpenFlags::empty(),
+        OpenMode::RDONLY,
+    )
+    .and_then(crate::fs::File::into_async)
+    .expect("Failed to open /proc/cpuinfo")
+    .buffer::<512>();
+
+    let mut siblings = None;
+    let mut lines = file.inline_lines::<128>();
+    while let Some(line) = lines.next().await {
+        if let Some(n) = line.unwrap().strip_prefix("siblings\t:") {
+            siblings = Some(n.trim().parse().unwrap());
+            break;
+        }
+    }
+    assert_eq!(siblings, Some(ncpu().unwrap()));
+
+    // "processor\t: 0\n" does not fit
+    let mut file = crate::fs::File::open(
+        const_cstr!("/proc/cpuinfo"),
+        OpenFlags::empty(),
+        OpenMode::RDONLY,
+    )
+    .and_then(crate::fs::File::into_async)
+    .expect("Failed to open /proc/cpuinfo")
+    .buffer::<8>();
+
+    let mut line = String::new();
+    assert!(matches!(
+        file.read_line(&mut line).await,
+        Err(crate::io::Error::LineTooLong)
+    ));
+    assert!(line.is_empty());
+
+    info!("async fds work");
+
+    let (tx, rx) = oneshot::channel();
+    crate::thread::spawn(
+        move || {
+            crate::syscalls::sleep(Duration::from_millis(20)).unwrap();
+            tx.send(42).unwrap();
+        },
+        None,
+    )
+    .expect("Failed to spawn thread");
+    assert_eq!(rx.await, Ok(42));
+
+    let (tx, rx) = oneshot::channel::<()>();
+    drop(tx);
+    assert_eq!(rx.await, Err(RecvError));
+
+    // The producer has to wait for the receiver most of the time
+    let (tx, mut rx) = mpsc::channel(4);
+    crate::thread::spawn(
+        move || {
+            executor::block_on(async move {
+                for i in 0..100 {
+                    tx.send(i).await.unwrap();
+                }
+            })
+        },
+        None,
+    )
+    .expect("Failed to spawn thread");
+
+    let mut sum = 0;
+    while let Some(i) = rx.recv().await {
+        sum += i;
+    }
+    assert_eq!(sum, 4950);
+
+    let (tx, mut rx) = mpsc::channel(1);
+    assert_eq!(tx.try_send(1), Ok(()));
+    assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+    assert_eq!(rx.try_recv(), Ok(1));
+    rx.close();
+    assert_eq!(tx.send(3).await, Err(SendError(3)));
+
+    let (tx, mut rx) = mpsc::unbounded();
+    for i in 0..10 {
+        tx.clone().send(i).unwrap();
+    }
+    drop(tx);
+    for i in 0..10 {
+        assert_eq!(rx.recv().await, Some(i));
+    }
+    assert_eq!(rx.recv().await, None);
+
+    let (tx, mut rx1) = broadcast::channel(2);
+    let mut rx2 = tx.subscribe();
+    for i in 1..=3 {
+        assert_eq!(tx.send(i), Ok(2));
+    }
+    assert_eq!(rx1.recv().await, Err(broadcast::RecvError::Lagged(1)));
+    assert_eq!(rx1.recv().await, Ok(2));
+    assert_eq!(rx1.recv().await, Ok(3));
+    drop(tx);
+    assert_eq!(rx1.recv().await, Err(broadcast::RecvError::Closed));
+    assert_eq!(rx2.try_recv(), Err(broadcast::TryRecvError::Lagged(1)));
+
+    // Values only need to be `Send`
+    type Job = Box<dyn FnOnce() -> i32 + Send>;
+
+    let (tx, rx) = oneshot::channel::<Job>();
+    assert!(tx.send(Box::new(|| 1)).is_ok());
+    assert_eq!(rx.await.unwrap()(), 1);
+
+    let (tx, mut rx) = mpsc::channel::<Job>(1);
+    assert!(tx.send(Box::new(|| 2)).await.is_ok());
+    assert_eq!(rx.recv().await.unwrap()(), 2);
+
+    info!("channels work");
+
+    let start = executor::now();
+    let (a, b, c) = executor::join!(
+        async {
+            executor::sleep(Duration::from_millis(30)).await;
+            1
+        },
+        async {
+            executor::sleep(Duration::from_millis(30)).await;
+            2
+        },
+        async { 3 },
+    );
+    assert_eq!((a, b, c), (1, 2, 3));
+    assert!(executor::now() - start < Duration::from_millis(60));
+
+    struct SetOnDrop(Arc<AtomicBool>);
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let dropped = Arc::new(AtomicBool::new(false));
+    let guard = SetOnDrop(dropped.clone());
+    let (_tx, rx) = oneshot::channel::<u32>();
+    let winner = executor::select! {
+        value = async move {
+            let _guard = guard;
+            rx.await
+        } => value.ok(),
+        () = executor::sleep(Duration::from_millis(10)) => {
+            // the other branch is cancelled before we get here
+            assert!(dropped.load(Ordering::SeqCst));
+            Some(2)
+        },
+    };
+    assert_eq!(winner, Some(2));
+
+    let sleepy = |i: u64| async move {
+        executor::sleep(Duration::from_millis(10 * (5 - i))).await;
+        i
+    };
+
+    assert_eq!(
+        executor::join_all((0..5).map(sleepy)).await,
+        (0..5).collect::<Vec<_>>()
+    );
+
+    let mut set: executor::FuturesUnordered<_> = (0..5).map(sleepy).collect();
+    assert_eq!(set.len(), 5);
+    let mut order = Vec::new();
+    while let Some(i) = set.next().await {
+        order.push(i);
+    }
+    assert_eq!(order, [4, 3, 2, 1, 0]);
+    assert!(set.is_empty());
+
+    info!("combinators work");
+
+    if let Err(err) = executor::uring::nop().await {
+        warn!("io_uring is not available: {}", err);
+
+        return 0;
+    }
+
+    // dropped before it completed, the driver cleans up after it
+    drop(executor::uring::nop());
+
+    let file = crate::fs::File::open(
+        const_cstr!("/proc/self/exe"),
+        OpenFlags::empty(),
+        OpenMode::RDONLY,
+    )
+    .expect("Failed to open /proc/self/exe");
+
+    let mut expected = [0; 4096];
+    assert_eq!(file.read_exact(&mut expected).unwrap(), expected.len());
+
+    // All reads are queued before the first one is awaited, so they are submitted at once
+    let reads: Vec<_> = (0..8)
+        .map(|i| executor::uring::read_at(file.fd(), Vec::with_capacity(512), i * 512))
+        .collect();
+
+    for (i, read) in reads.into_iter().enumerate() {
+        let (res, buf) = read.await;
+
+        assert_eq!(res.unwrap(), 512);
+        assert_eq!(buf, expected[i * 512..(i + 1) * 512]);
+    }
+
+    drop(file);
+
+    let event_fd = unsafe { crate::syscalls::eventfd2(0, crate::syscalls::EventFdFlags::CLOEXEC) }
+        .map(Fd)
+        .expect("Failed to create eventfd");
+
+    let (res, _) = executor::uring::write_at(event_fd, 4u64.to_ne_bytes().to_vec(), u64::MAX).await;
+    assert_eq!(res.unwrap(), 8);
+
+    let (res, buf) = executor::uring::read_at(event_fd, Vec::with_capacity(8), u64::MAX).await;
+    assert_eq!(res.unwrap(), 8);
+    assert_eq!(buf, 4u64.to_ne_bytes());
+
+    crate::syscalls::close(event_fd).unwrap();
+
+    info!("io_uring works");
+
     0
 }
 
 unsafe fn user_input_main(_env: Environment) -> i8 {
     println!("Hello, World!");
 
-    print!("> ");
-    for line in stdin().lines() {
-        let line = line.unwrap();
+    let executor = crate::executor::init(1);
 
-        dbg!(line);
+    executor.block_on(async {
+        let stdout = async_stdout().expect("Failed to open stdout");
+        let mut stdin = async_stdin().expect("Failed to open stdin");
 
-        print!("> ");
-    }
+        stdout.write_all(b"> ").await.unwrap();
+
+        let mut lines = stdin.lines();
+        while let Some(line) = lines.next().await {
+            let line = line.unwrap();
+
+            dbg!(line);
+
+            stdout.write_all(b"> ").await.unwrap();
+        }
+    });
 
     0
 }
@@ -198,9 +922,699 @@ unsafe fn alloc_test_main(_env: Environment) -> i8 {
         }
     }
 
+    // Grow from a single chunk up to a remapped allocation, one byte at a time
+    let mut v: Vec<u8> = Vec::new();
+    for i in 0..256 * 1024 {
+        v.push(i as u8);
+        if i % 1000 == 0 {
+            v.shrink_to_fit();
+        }
+    }
+    assert!(v.iter().enumerate().all(|(i, &x)| x == i as u8));
+
+    // Shrink back down into a block
+    v.truncate(100);
+    v.shrink_to_fit();
+    v.truncate(10);
+    v.shrink_to_fit();
+    assert!(v.iter().enumerate().all(|(i, &x)| x == i as u8));
+
+    // Grow and shrink across `HUGE_PAGE_SIZE`, where mappings may be huge pages
+    let mut v: Vec<u64> = Vec::new();
+    for i in 0..crate::allocator::HUGE_PAGE_SIZE / 2 {
+        v.push(i as u64);
+    }
+    assert!(v.iter().enumerate().all(|(i, &x)| x == i as u64));
+    v.truncate(1000);
+    v.shrink_to_fit();
+    assert!(v.iter().enumerate().all(|(i, &x)| x == i as u64));
+    drop(v);
+
+    // Freed memory is filled with `UNALLOCATED_DATA_SENTINEL`, which must not
+    // show up in zeroed allocations
+    drop(alloc::vec![1u8; 1000]);
+    assert!(alloc::vec![0u8; 1000].iter().all(|&x| x == 0));
+    assert!(alloc::vec![0u8; 1 << 20].iter().all(|&x| x == 0));
+    assert!(alloc::vec![0u8; 3 << 21].iter().all(|&x| x == 0));
+
+    // Bypass our thread's cache, so frees show up in the stats right away
+    crate::allocator::set_thread_caches(false);
+
+    let before = crate::allocator::stats();
+
+    let boxes: Vec<_> = (0..100).map(|_| Box::new([0u8; 100])).collect();
+    let large = Vec::<u8>::with_capacity(1 << 20);
+
+    // keep the allocations from being optimized out
+    dbg!(boxes.as_ptr(), large.as_ptr());
+
+    let during = crate::allocator::stats();
+    dbg!(during);
+
+    // 100 128 byte chunks, and the `Vec` holding them
+    assert!(during.bytes_allocated >= before.bytes_allocated + 100 * 128);
+    assert!(during.blocks_in_use[7] >= 1);
+    assert!(during.header_bytes > 0);
+    assert_eq!(during.mmap_allocations, before.mmap_allocations + 1);
+    assert!(during.mmap_bytes >= before.mmap_bytes + (1 << 20));
+    assert!(during.peak_bytes >= during.bytes_allocated + during.mmap_bytes);
+
+    drop((boxes, large));
+
+    let after = crate::allocator::stats();
+    assert_eq!(after.bytes_allocated, before.bytes_allocated);
+    assert_eq!(after.mmap_allocations, before.mmap_allocations);
+    assert_eq!(after.mmap_bytes, before.mmap_bytes);
+    assert_eq!(after.peak_bytes, during.peak_bytes);
+
+    crate::allocator::set_thread_caches(true);
+
+    alloc_layouts_test();
+    local_alloc_test();
+
+    0
+}
+
+fn local_alloc_test() {
+    use crate::allocator::{Arena, Pool};
+    use core::alloc::{Allocator, Layout};
+
+    let mut arena = Arena::new(1 << 20).unwrap();
+
+    {
+        let a = Box::new_in(1u8, &arena);
+        let b = Box::new_in(2u64, &arena);
+        assert_eq!(b.as_ref() as *const u64 as usize % 8, 0);
+        assert_eq!((*a, *b), (1, 2));
+
+        // The latest allocation grows in place
+        let mut v = Vec::new_in(&arena);
+        v.extend(0..10_000u32);
+        assert!(v.iter().copied().eq(0..10_000));
+        assert!(arena.used() < 2 * 10_000 * 4);
+
+        // Allocations fail once the reservation is used up
+        assert!((&arena).allocate(Layout::new::<[u8; 1 << 20]>()).is_err());
+    }
+
+    arena.reset().unwrap();
+    assert_eq!(arena.used(), 0);
+
+    let pool = Pool::<[u64; 4]>::new(3).unwrap();
+
+    let a = Box::new_in([1u64; 4], &pool);
+    let b = Box::new_in([2u64; 4], &pool);
+    let c = Box::new_in([3u64; 4], &pool);
+    assert!((&pool).allocate(Layout::new::<[u64; 4]>()).is_err());
+    assert!((&pool).allocate(Layout::new::<[u64; 5]>()).is_err());
+
+    // freed slots are reused
+    let b_ptr = &*b as *const [u64; 4];
+    drop(b);
+    let d = Box::new_in([4u64; 4], &pool);
+    assert_eq!(&*d as *const [u64; 4], b_ptr);
+    assert_eq!((a[0], c[0], d[0]), (1, 3, 4));
+
+    info!("arenas and pools work");
+}
+
+/// Allocate and resize every size on both sides of `MMAP_THRESHOLD` with every alignment
+/// up to a page, keeping all allocations of an alignment alive at once
+unsafe fn alloc_layouts_test() {
+    use alloc::alloc::{alloc, alloc_zeroed, dealloc, realloc};
+    use core::alloc::Layout;
+
+    const MAX_SIZE: usize = 4096;
+
+    for align in (0..=12).map(|shift| 1 << shift) {
+        let layout = |size| Layout::from_size_align(size, align).unwrap();
+
+        let mut allocations: Vec<(*mut u8, usize)> = (1..MAX_SIZE)
+            .map(|size| {
+                let ptr = if size % 2 == 0 {
+                    alloc(layout(size))
+                } else {
+                    let ptr = alloc_zeroed(layout(size));
+                    assert!((0..size).all(|i| *ptr.add(i) == 0));
+                    ptr
+                };
+
+                assert!(!ptr.is_null(), "failed to allocate {:?}", layout(size));
+                assert_eq!(
+                    ptr.align_offset(align),
+                    0,
+                    "{:?} is misaligned",
+                    layout(size)
+                );
+
+                ptr.write_bytes(size as u8, size);
+
+                (ptr, size)
+            })
+            .collect();
+
+        for (ptr, size) in allocations.iter_mut() {
+            let new_size = if *size % 3 == 0 { *size / 3 } else { *size * 2 };
+
+            let new_ptr = realloc(*ptr, layout(*size), new_size);
+
+            assert!(!new_ptr.is_null());
+            assert_eq!(new_ptr.align_offset(align), 0);
+            assert!(
+                (0..(*size).min(new_size)).all(|i| *new_ptr.add(i) == *size as u8),
+                "resizing {:?} to {} bytes lost its data",
+                layout(*size),
+                new_size
+            );
+
+            *ptr = new_ptr;
+            *size = new_size;
+        }
+
+        for &(ptr, size) in allocations.iter() {
+            dealloc(ptr, layout(size));
+        }
+    }
+
+    info!("all layouts work");
+}
+
+/// Run `f` in a child process and return its exit code
+unsafe fn exit_code_of(f: impl FnOnce()) -> i32 {
+    let pid = crate::syscalls::fork().unwrap();
+
+    if pid == 0 {
+        f();
+        crate::syscalls::exit(0);
+    }
+
+    let mut status = 0;
+    crate::syscalls::wait4(pid, &mut status, 0, core::ptr::null_mut()).unwrap();
+
+    (status >> 8) & 0xff
+}
+
+unsafe fn alloc_debug_main(_env: Environment) -> i8 {
+    use alloc::alloc::{alloc, dealloc};
+    use core::alloc::Layout;
+
+    assert!(
+        crate::allocator::debug_checks(),
+        "the allocator is not in debug mode"
+    );
+
+    let layout = Layout::new::<[u64; 8]>();
+
+    // Correct use must not trip any of the checks
+    let ok = exit_code_of(|| {
+        let mut v: Vec<Box<[u64; 8]>> = (0..1000).map(|i| Box::new([i; 8])).collect();
+        v.truncate(10);
+        v.shrink_to_fit();
+        v.extend((0..1000).map(|i| Box::new([i; 8])));
+        drop(v);
+
+        let mut s = alloc::string::String::new();
+        for _ in 0..10_000 {
+            s.push('x');
+        }
+    });
+    assert_eq!(ok, 0);
+
+    let double_free = exit_code_of(|| {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout);
+    });
+    assert_ne!(double_free, 0);
+
+    let use_after_free = exit_code_of(|| {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        ptr.write_volatile(1);
+
+        // push `ptr` out of the quarantine and reuse it
+        for _ in 0..10_000 {
+            dealloc(alloc(layout), layout);
+        }
+    });
+    assert_ne!(use_after_free, 0);
+
+    let wrong_layout = exit_code_of(|| {
+        let ptr = alloc(layout);
+        dealloc(ptr, Layout::new::<[u64; 32]>());
+    });
+    assert_ne!(wrong_layout, 0);
+
+    // The second of the two chunks would stay allocated
+    let too_small_layout = exit_code_of(|| {
+        let ptr = alloc(Layout::from_size_align(20, 8).unwrap());
+        dealloc(ptr, Layout::from_size_align(16, 8).unwrap());
+    });
+    assert_ne!(too_small_layout, 0);
+
+    let wrong_pointer = exit_code_of(|| {
+        let ptr = alloc(layout);
+        dealloc(ptr.add(8), Layout::new::<u64>());
+    });
+    assert_ne!(wrong_pointer, 0);
+
+    info!("allocator debug checks work");
+
+    0
+}
+
+unsafe fn alloc_bench_main(_env: Environment) -> i8 {
+    const N_THREADS: usize = 16;
+
+    crate::allocator::set_thread_caches(false);
+    let locked = alloc_bench(N_THREADS);
+
+    crate::allocator::set_thread_caches(true);
+    if !crate::allocator::thread_caches_active() {
+        warn!("per-thread caches are not available (no FSGSBASE), expect no speedup");
+    }
+    let cached = alloc_bench(N_THREADS);
+
+    info!(
+        "{} threads: per-thread caches: {:?}, global lock only: {:?} ({:.2}x)",
+        N_THREADS,
+        cached,
+        locked,
+        locked.as_secs_f64() / cached.as_secs_f64()
+    );
+
+    // Finding a block with free chunks should not depend on the size of the heap
+    crate::allocator::set_thread_caches(false);
+    let small_heap = alloc_bench(1);
+
+    // about 2000 full blocks of 1 KiB chunks
+    let heap: Vec<_> = (0..30_000).map(|_| Box::new([0u8; 1000])).collect();
+    let large_heap = alloc_bench(1);
+    drop(heap);
+
+    crate::allocator::set_thread_caches(true);
+
+    info!(
+        "small heap: {:?}, large heap: {:?} ({:.2}x)",
+        small_heap,
+        large_heap,
+        large_heap.as_secs_f64() / small_heap.as_secs_f64()
+    );
+
     0
 }
 
+fn alloc_bench(n_threads: usize) -> Duration {
+    const N_ROUNDS: usize = 5_000;
+    const ROUND_SIZE: usize = 64;
+
+    let start = executor::now();
+
+    let handles: Vec<_> = (0..n_threads)
+        .map(|_| {
+            crate::thread::spawn(
+                || {
+                    let mut boxes = Vec::with_capacity(ROUND_SIZE);
+
+                    for i in 0..N_ROUNDS {
+                        for j in 0..ROUND_SIZE {
+                            boxes.push(Box::new([(i + j) as u8; 24]));
+                        }
+
+                        for (j, b) in boxes.drain(..).enumerate() {
+                            assert_eq!(b[0], (i + j) as u8);
+                        }
+                    }
+                },
+                None,
+            )
+            .expect("Failed to spawn thread")
+        })
+        .collect();
+
+    for mut handle in handles {
+        assert!(handle.join().unwrap().is_some());
+    }
+
+    executor::now() - start
+}
+
+/// Readers share the lock and never see a half-finished write
+fn rwlock_test() {
+    const N_READERS: usize = 16;
+    const N_WRITES: usize = 10_000;
+
+    // Safety: The RwLock is `Pin`ned by the `Arc::pin`.
+    let lock = Arc::pin(unsafe { RwLock::new((0usize, 0usize)) });
+
+    {
+        let a = lock.read();
+        let b = lock.read();
+        assert_eq!(*a, *b);
+    }
+
+    let readers: Vec<_> = (0..N_READERS)
+        .map(|_| {
+            let lock = lock.clone();
+
+            crate::thread::spawn(
+                move || {
+                    let mut n_reads = 0;
+
+                    loop {
+                        let (a, b) = *lock.read();
+                        assert_eq!(a, b, "read a half-finished write");
+
+                        n_reads += 1;
+
+                        if a == N_WRITES {
+                            return n_reads;
+                        }
+                    }
+                },
+                None,
+            )
+            .expect("Failed to spawn thread")
+        })
+        .collect();
+
+    for _ in 0..N_WRITES {
+        let mut data = lock.write();
+        data.0 += 1;
+        data.1 += 1;
+    }
+
+    for mut handle in readers {
+        assert!(handle.join().unwrap().unwrap() > 0);
+    }
+
+    assert_eq!(*lock.read(), (N_WRITES, N_WRITES));
+
+    info!("rwlock works");
+}
+
+/// Waiters see every notification, and time out without one
+fn condvar_test() {
+    const N_WAITERS: usize = 8;
+
+    struct Shared {
+        started: Mutex<usize>,
+        go: Mutex<bool>,
+        condvar: Condvar,
+    }
+
+    // Safety: The Mutexes are `Pin`ned by the `Arc::pin`.
+    let shared = unsafe {
+        Arc::pin(Shared {
+            started: Mutex::new(0),
+            go: Mutex::new(false),
+            condvar: Condvar::new(),
+        })
+    };
+
+    let start = executor::now();
+    let (guard, timed_out) = shared
+        .condvar
+        .wait_timeout(shared.go.lock(), Duration::from_millis(50));
+    assert!(timed_out && !*guard);
+    assert!(executor::now() - start >= Duration::from_millis(50));
+    drop(guard);
+
+    let waiters: Vec<_> = (0..N_WAITERS)
+        .map(|_| {
+            let shared = shared.clone();
+
+            crate::thread::spawn(
+                move || {
+                    *shared.started.lock() += 1;
+                    shared.condvar.notify_all();
+
+                    let go = shared.condvar.wait_while(shared.go.lock(), |go| !*go);
+                    assert!(*go);
+                },
+                None,
+            )
+            .expect("Failed to spawn thread")
+        })
+        .collect();
+
+    drop(
+        shared
+            .condvar
+            .wait_while(shared.started.lock(), |started| *started < N_WAITERS),
+    );
+
+    *shared.go.lock() = true;
+    shared.condvar.notify_all();
+
+    for mut handle in waiters {
+        assert!(handle.join().unwrap().is_some());
+    }
+
+    info!("condvar works");
+}
+
+/// Racing threads initialize a `Lazy` static exactly once, and all see the same value
+fn once_test() {
+    const N_THREADS: usize = 8;
+
+    static N_INITS: AtomicUsize = AtomicUsize::new(0);
+    static TABLE: Lazy<Vec<usize>> = Lazy::new(|| {
+        N_INITS.fetch_add(1, Ordering::Relaxed);
+
+        // give the other threads time to start waiting
+        crate::syscalls::sleep(Duration::from_millis(10)).unwrap();
+
+        (0..1000).collect()
+    });
+
+    let handles: Vec<_> = (0..N_THREADS)
+        .map(|_| {
+            crate::thread::spawn(|| TABLE.as_ptr() as usize, None).expect("Failed to spawn thread")
+        })
+        .collect();
+
+    for mut handle in handles {
+        assert_eq!(handle.join().unwrap(), Some(TABLE.as_ptr() as usize));
+    }
+
+    assert_eq!(N_INITS.load(Ordering::Relaxed), 1);
+    assert_eq!(TABLE.iter().sum::<usize>(), 999 * 1000 / 2);
+
+    let once = Once::new();
+    let mut n_calls = 0;
+    once.call_once(|| n_calls += 1);
+    once.call_once(|| n_calls += 1);
+    assert_eq!(n_calls, 1);
+    assert!(once.is_completed());
+
+    let cell = OnceCell::new();
+    assert_eq!(cell.get(), None);
+    assert_eq!(cell.get_or_try_init(|| Err(())), Err(()));
+    assert_eq!(cell.get(), None);
+    assert_eq!(cell.set(Box::new(1)), Ok(()));
+    assert_eq!(cell.set(Box::new(2)), Err(Box::new(2)));
+    assert_eq!(cell.get_or_init(|| Box::new(3)), &Box::new(1));
+    assert_eq!(cell.into_inner(), Some(Box::new(1)));
+
+    info!("once works");
+}
+
+/// A semaphore bounds how many threads run at once, and a barrier separates phases
+fn barrier_and_semaphore_test() {
+    const N_THREADS: usize = 8;
+    const N_PERMITS: u32 = 3;
+    const N_PHASES: usize = 10;
+
+    struct Shared {
+        semaphore: Semaphore,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        barrier: Barrier,
+        phase: AtomicUsize,
+        n_leaders: AtomicUsize,
+    }
+
+    let shared = Arc::new(Shared {
+        semaphore: Semaphore::new(N_PERMITS),
+        running: AtomicUsize::new(0),
+        max_running: AtomicUsize::new(0),
+        barrier: Barrier::new(N_THREADS as u32),
+        phase: AtomicUsize::new(0),
+        n_leaders: AtomicUsize::new(0),
+    });
+
+    let handles: Vec<_> = (0..N_THREADS)
+        .map(|_| {
+            let shared = shared.clone();
+
+            crate::thread::spawn(
+                move || {
+                    for phase in 0..N_PHASES {
+                        {
+                            let _permit = shared.semaphore.acquire();
+
+                            let running = shared.running.fetch_add(1, Ordering::SeqCst) + 1;
+                            shared.max_running.fetch_max(running, Ordering::SeqCst);
+
+                            crate::syscalls::sleep(Duration::from_millis(1)).unwrap();
+
+                            shared.running.fetch_sub(1, Ordering::SeqCst);
+                        }
+
+                        // Nobody may be ahead of the others
+                        assert_eq!(shared.phase.load(Ordering::SeqCst), phase);
+
+                        if shared.barrier.wait() {
+                            shared.n_leaders.fetch_add(1, Ordering::SeqCst);
+                            shared.phase.fetch_add(1, Ordering::SeqCst);
+                        }
+
+                        // The leader's update is only seen by everyone after another barrier
+                        shared.barrier.wait();
+                    }
+                },
+                None,
+            )
+            .expect("Failed to spawn thread")
+        })
+        .collect();
+
+    for mut handle in handles {
+        assert!(handle.join().unwrap().is_some());
+    }
+
+    assert!(shared.max_running.load(Ordering::SeqCst) <= N_PERMITS as usize);
+    assert_eq!(shared.n_leaders.load(Ordering::SeqCst), N_PHASES);
+    assert_eq!(shared.semaphore.available_permits(), N_PERMITS);
+
+    let semaphore = Semaphore::new(1);
+    let permit = semaphore.try_acquire();
+    assert!(permit.is_some());
+    assert!(semaphore.try_acquire().is_none());
+    drop(permit);
+    assert!(semaphore.try_acquire().is_some());
+
+    info!("barrier and semaphore work");
+}
+
+fn channel_test() {
+    const N_SENDERS: usize = 4;
+    const N_VALUES: usize = 1000;
+
+    let (tx, rx) = sync::channel();
+
+    let handles: Vec<_> = (0..N_SENDERS)
+        .map(|i| {
+            let tx = tx.clone();
+
+            crate::thread::spawn(
+                move || {
+                    for j in 0..N_VALUES {
+                        tx.send(i * N_VALUES + j).unwrap();
+                    }
+                },
+                None,
+            )
+            .expect("Failed to spawn thread")
+        })
+        .collect();
+
+    drop(tx);
+
+    // `iter` ends once every sender is dropped
+    let mut received: Vec<usize> = rx.iter().collect();
+    received.sort_unstable();
+    assert!(received.into_iter().eq(0..N_SENDERS * N_VALUES));
+    assert_eq!(rx.try_recv(), Err(sync::channel::TryRecvError::Closed));
+
+    for mut handle in handles {
+        assert!(handle.join().unwrap().is_some());
+    }
+
+    // A full bounded channel blocks the sender until a value is received
+    let (tx, rx) = sync::sync_channel(1);
+    tx.send(0).unwrap();
+    assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+
+    let mut handle = crate::thread::spawn(
+        move || {
+            for i in 1..=N_VALUES {
+                tx.send(i).unwrap();
+            }
+
+            // The receiver is dropped after the last value
+            while !tx.is_closed() {
+                crate::syscalls::sleep(Duration::from_millis(1)).unwrap();
+            }
+            assert_eq!(tx.send(0), Err(SendError(0)));
+        },
+        None,
+    )
+    .expect("Failed to spawn thread");
+
+    for i in 0..=N_VALUES {
+        assert_eq!(rx.recv(), Ok(i));
+    }
+    drop(rx);
+
+    assert!(handle.join().unwrap().is_some());
+
+    // Timeouts, and receivers that are cloned
+    let (tx, rx) = sync::sync_channel::<u32>(4);
+    let rx2 = rx.clone();
+
+    let start = crate::syscalls::clock_gettime(crate::syscalls::ClockId::Monotonic).unwrap();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(50)),
+        Err(sync::channel::RecvTimeoutError::Timeout)
+    );
+    let elapsed =
+        crate::syscalls::clock_gettime(crate::syscalls::ClockId::Monotonic).unwrap() - start;
+    assert!(elapsed >= Duration::from_millis(50));
+    assert_eq!(rx2.try_recv(), Err(sync::channel::TryRecvError::Empty));
+
+    tx.send(1).unwrap();
+    tx.send(2).unwrap();
+    assert_eq!(rx2.recv_timeout(Duration::from_millis(50)), Ok(1));
+    assert_eq!(rx.recv(), Ok(2));
+
+    // `Duration::MAX` means no timeout
+    tx.send(3).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::MAX), Ok(3));
+
+    drop(tx);
+    assert_eq!(rx.recv(), Err(RecvError));
+    assert_eq!(
+        rx2.recv_timeout(Duration::from_millis(50)),
+        Err(sync::channel::RecvTimeoutError::Closed)
+    );
+
+    // Values only need to be `Send`, e.g. jobs for a worker thread
+    let (jobs, job_rx) = sync::channel::<Box<dyn FnOnce() -> usize + Send>>();
+    let (results, result_rx) = sync::sync_channel(1);
+
+    let mut worker = crate::thread::spawn(
+        move || {
+            for job in job_rx.iter() {
+                results.send(job()).unwrap();
+            }
+        },
+        None,
+    )
+    .expect("Failed to spawn thread");
+
+    for i in 0..10 {
+        assert!(jobs.send(Box::new(move || i * 2)).is_ok());
+    }
+    drop(jobs);
+
+    assert_eq!(result_rx.iter().sum::<usize>(), 90);
+    assert!(worker.join().unwrap().is_some());
+
+    info!("channels work");
+}
+
 unsafe fn thread_test_main(_env: Environment) -> i8 {
     const N_LOOPS: usize = 2_000_000;
     const N_THREADS: usize = 16;
@@ -212,17 +1626,25 @@ unsafe fn thread_test_main(_env: Environment) -> i8 {
     // Safety: The Mutex is `Pin`ned by the `Arc::pin`.
     let data = Arc::pin(Mutex::new(0));
 
+    // Start everyone at once, so that the threads actually contend for the lock
+    let start = Arc::new(Barrier::new(N_THREADS as u32 + 1));
+    let finished = Arc::new(CountDownLatch::new(N_THREADS as u32));
+
     // dbg!(data.deref() as *const _);
 
     let handles: Vec<_> = (0..N_THREADS)
         .into_iter()
         .map(|i| {
             let data = data.clone();
+            let start = start.clone();
+            let finished = finished.clone();
 
             crate::thread::spawn(
                 move || {
                     info!("child {:X?}...", i);
 
+                    start.wait();
+
                     // dbg!(data.deref() as *const _);
 
                     for _ in 0..N_LOOPS {
@@ -231,6 +1653,8 @@ unsafe fn thread_test_main(_env: Environment) -> i8 {
 
                     info!("child {:X?} done", i);
 
+                    finished.count_down();
+
                     42
                 },
                 None,
@@ -239,19 +1663,29 @@ unsafe fn thread_test_main(_env: Environment) -> i8 {
         })
         .collect();
 
+    start.wait();
+
     for _ in 0..(N_THREADS * N_LOOPS) {
         *data.lock() -= 1;
     }
 
     info!("parent waiting...");
 
+    finished.wait();
+
+    assert_eq!(*data.lock(), 0);
+
     for mut handle in handles {
         assert_eq!(handle.join().unwrap(), Some(42));
     }
 
     info!("parent done");
 
-    assert_eq!(*data.lock(), 0);
+    rwlock_test();
+    condvar_test();
+    once_test();
+    barrier_and_semaphore_test();
+    channel_test();
 
     info!("sleeping...");
 
@@ -12,10 +12,11 @@ use core::{
 use crate::{
     stack_protection::setup_alt_stack,
     start::RUNTIME_OPTIONS,
+    sync::Mutex,
     syscalls::{self, futex_wait, munmap},
-    tls::{setup_tls, teardown_tls, Tls},
+    tls::{clear_inherited_tls, setup_tls, teardown_tls, Tls},
 };
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use syscalls::{helper::SyscallErrorKind, CloneArgs, CloneFlags, SyscallResult};
 
 /// default stack size (4Mib)
@@ -31,9 +32,34 @@ struct JoinHandleInner<T> {
 
 impl<T> Drop for JoinHandleInner<T> {
     fn drop(&mut self) {
-        unsafe {
-            Box::from_raw(self.child_tid_futex as *mut AtomicU32);
+        let futex = unsafe { Box::from_raw(self.child_tid_futex as *mut AtomicU32) };
+
+        // The kernel clears the futex once the thread exits, so it must
+        // outlive the thread
+        if futex.load(Ordering::SeqCst) != 0 {
+            ORPHANED_FUTEXES.lock().push(futex);
         }
+
+        free_orphaned_futexes();
+    }
+}
+
+/// The `child_tid_futex`es of threads that were still running when their
+/// `JoinHandleInner` was dropped
+///
+/// # Safety
+/// `Pin`ed, since it is a static
+static ORPHANED_FUTEXES: Mutex<Vec<Box<AtomicU32>>> = unsafe { Mutex::new(Vec::new()) };
+
+/// Free the futexes of threads that exited after their `JoinHandle` was dropped
+pub fn free_orphaned_futexes() {
+    let mut orphans = ORPHANED_FUTEXES.lock();
+
+    orphans.retain(|futex| futex.load(Ordering::SeqCst) != 0);
+
+    if orphans.is_empty() {
+        // free the buffer as well, so that it does not show up as a leak
+        *orphans = Vec::new();
     }
 }
 
@@ -266,15 +292,20 @@ where
                     let child_stack_allocation = payload.inner.child_stack_allocation;
                     let allocated_size = payload.inner.allocated_size;
 
+                    if RUNTIME_OPTIONS.tls {
+                        // we share our parent's `gs` until we set up our own TLS
+                        clear_inherited_tls().expect("Failed to clear inherited tls");
+                    }
+
                     if RUNTIME_OPTIONS.segv_handling {
                         setup_alt_stack().expect("Failed to set up a signal handling stack");
                     }
 
                     if RUNTIME_OPTIONS.tls {
-                        setup_tls(Tls {
-                            stack_base: child_stack_allocation.add(allocated_size),
-                            stack_limit: allocated_size,
-                        })
+                        setup_tls(Tls::new(
+                            child_stack_allocation.add(allocated_size),
+                            allocated_size,
+                        ))
                         .expect("Failed to setup tls");
                     }
 
@@ -1,6 +1,19 @@
 use alloc::boxed::Box;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use crate::syscalls::{arch_prctl, PrctlCode, SyscallResult};
+use crate::{
+    allocator::ThreadCache,
+    env::{Environment, AT_HWCAP2},
+    syscalls::{arch_prctl, PrctlCode, SyscallResult},
+};
+
+/// Set in `AT_HWCAP2` if the kernel allows user space to use `rdgsbase`
+const HWCAP2_FSGSBASE: usize = 1 << 1;
+
+static FSGSBASE: AtomicBool = AtomicBool::new(false);
 
 unsafe fn get_gs() -> SyscallResult<u64> {
     let mut gs: u64 = 0;
@@ -20,6 +33,29 @@ unsafe fn set_gs(gs: u64) -> SyscallResult<()> {
 pub struct Tls {
     pub stack_base: *mut u8,
     pub stack_limit: usize,
+    /// Free chunks of the global allocator, owned by this thread
+    pub alloc_cache: ThreadCache,
+}
+
+impl Tls {
+    pub const fn new(stack_base: *mut u8, stack_limit: usize) -> Self {
+        Self {
+            stack_base,
+            stack_limit,
+            alloc_cache: ThreadCache::new(),
+        }
+    }
+}
+
+/// Check whether the TLS pointer can be read without a syscall.
+/// Should be called once, before any thread sets up its TLS.
+pub fn init(env: &Environment) {
+    let fsgsbase = env
+        .aux_value(AT_HWCAP2)
+        .map(|hwcap2| hwcap2 & HWCAP2_FSGSBASE != 0)
+        .unwrap_or(false);
+
+    FSGSBASE.store(fsgsbase, Ordering::Relaxed);
 }
 
 // TODO: mmap a page here to that TLS can be used inside of the allocator?
@@ -31,6 +67,35 @@ pub unsafe fn get_tls_ptr() -> SyscallResult<*mut Tls> {
     get_gs().map(|gs| gs as *mut Tls)
 }
 
+/// Returns true if `try_get_tls_ptr_fast` can find the TLS
+pub fn has_fast_tls_ptr() -> bool {
+    FSGSBASE.load(Ordering::Relaxed)
+}
+
+/// Returns the TLS of the current thread without making a syscall.
+/// Returns `None` if the TLS is not set up, or if it can't be read cheaply.
+#[inline]
+pub fn try_get_tls_ptr_fast() -> Option<*mut Tls> {
+    if !has_fast_tls_ptr() {
+        return None;
+    }
+
+    let gs: u64;
+
+    // Safety: the kernel enabled `rdgsbase` for user space
+    unsafe { asm!("rdgsbase {}", out(reg) gs, options(nomem, nostack, preserves_flags)) };
+
+    (gs != 0).then(|| gs as *mut Tls)
+}
+
+/// Forget the TLS inherited from the parent thread, without freeing it.
+///
+/// # Safety
+/// Must be called by new threads before they allocate, and before they set up their own TLS.
+pub unsafe fn clear_inherited_tls() -> SyscallResult<()> {
+    set_gs(0)
+}
+
 // Safety: invalidates all references to thread local data
 pub unsafe fn teardown_tls() -> SyscallResult<Tls> {
     let tls = get_tls_ptr()?;